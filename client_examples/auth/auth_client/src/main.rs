@@ -1,5 +1,5 @@
 use auth_lib::{AuthMessage, login, protected_function};
-use lirpc_rs_client::Client;
+use lirpc_rs_client::{Client, error::CallError};
 
 #[tokio::main]
 async fn main() {
@@ -12,15 +12,15 @@ async fn main() {
             password: "password".to_string(),
         },
     )
-    .await
-    .unwrap();
+    .await;
 
     match response {
         Ok(_) => println!("Authenticated"),
-        Err(e) => {
+        Err(CallError::Domain(e)) => {
             eprintln!("Failed to authenticate: {e:?}");
             return;
         }
+        Err(e) => panic!("{e}"),
     }
 
     let protected_response = protected_function(&mut client).await.unwrap();
@@ -1,6 +1,11 @@
 use lirpc_rs_client::{Client, transport::Transport};
 use serde::{Deserialize, Serialize};
 
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("auth_lib", "0.1.0", "861614061f05974e")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthMessage {
     pub username: String,
@@ -21,14 +26,14 @@ pub struct SecretMessage {
 pub async fn login<T, F>(
     client: &mut Client<T, F>,
     request: AuthMessage,
-) -> Result<Result<(), MyError>, lirpc_rs_client::error::Error>
+) -> Result<(), lirpc_rs_client::error::CallError<MyError>>
 where
     T: Transport<F>,
 {
     client
-        .call::<AuthMessage, Result<(), MyError>>("login".to_string(), Some(request))
+        .call::<AuthMessage, ()>("login".to_string(), Some(request))
         .await?
-        .resolve()
+        .resolve_with_error::<MyError>()
         .await
 }
 
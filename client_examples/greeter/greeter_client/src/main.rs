@@ -1,9 +1,10 @@
-use greeter_lib::{GreetingRequest, greet};
+use greeter_lib::{GreetingRequest, api_identity, greet};
 use lirpc_rs_client::Client;
 
 #[tokio::main]
 async fn main() {
     let mut client = Client::new_tcp_plain("127.0.0.1:5000").await.unwrap();
+    client.hello(Some(api_identity())).await.unwrap();
 
     let response = greet(
         &mut client,
@@ -1,6 +1,11 @@
 use lirpc_rs_client::{Client, transport::Transport};
 use serde::{Deserialize, Serialize};
 
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("greeter_lib", "0.1.0", "baeaa7dbb10cf56f")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GreetingRequest {
     pub name: String,
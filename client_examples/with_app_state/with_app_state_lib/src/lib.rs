@@ -1,6 +1,11 @@
 use lirpc_rs_client::{Client, transport::Transport};
 use serde::{Deserialize, Serialize};
 
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("with_app_state_lib", "0.1.0", "5049337cb399413c")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CountResponse {
     pub count: u64,
@@ -51,6 +51,8 @@ impl FromConnectionMessage<(), ConnectionState> for AuthRequired {
     }
 }
 
+// Serde attributes aren't part of the api spec, so clients generated from it
+// expect the default representation of `MyError` and fail to decode this one
 #[derive(LiRpcType, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum MyError {
@@ -86,7 +88,8 @@ async fn protected_function(AuthRequired(User(username)): AuthRequired) -> Secre
 #[tokio::main]
 async fn main() {
     let server = ServerBuilder::new()
-        .with_handlers(handlers!(login, protected_function))
+        // `protected_function` has to wait for a preceding `login` to complete
+        .with_sequential_handlers(handlers!(login, protected_function))
         .with_types(types!(AuthMessage, SecretMessage, MyError))
         .build_with_connection_state(ConnectionState::default);
 
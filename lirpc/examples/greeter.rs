@@ -28,6 +28,7 @@ async fn main() {
     let server = ServerBuilder::new()
         .with_handlers(handlers!(greet))
         .with_types(types!(GreetingRequest, GreetingResponse))
+        .with_api_identity("greeter_lib", env!("CARGO_PKG_VERSION"))
         .build();
 
     tracing::subscriber::set_global_default(
@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{translatable::Type, type_definition::TypeDefinition};
+use crate::{error::LiRpcProtocolError, translatable::Type, type_definition::TypeDefinition};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiSpec {
@@ -10,6 +10,20 @@ pub struct ApiSpec {
     pub version: String,
     pub methods: BTreeMap<String, LiRpcMethodSpec>,
     pub types: BTreeMap<String, TypeDefinition>,
+    /// The `error` tags of the protocol errors (see [`LiRpcProtocolError`])
+    /// any method of the api can respond with.
+    #[serde(default)]
+    pub protocol_errors: Vec<String>,
+}
+
+/// Identifies the api a server serves, or a client was generated for.
+/// Exchanged by the `lirpc_hello` handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiIdentity {
+    pub name: String,
+    pub version: String,
+    /// See [`ApiSpec::fingerprint`]
+    pub fingerprint: String,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -34,6 +48,10 @@ impl ApiSpec {
             version,
             methods,
             types,
+            protocol_errors: LiRpcProtocolError::KINDS
+                .iter()
+                .map(|kind| kind.to_string())
+                .collect(),
         };
 
         spec.validate()?;
@@ -52,6 +70,30 @@ impl ApiSpec {
         Ok(spec)
     }
 
+    /// A stable hash of the methods and types of the api, which tells apart clients generated
+    /// from another spec of the api. The name, version and protocol errors are left out.
+    pub fn fingerprint(&self) -> String {
+        // 64-bit FNV-1a, as the hash has to be the same across builds and platforms
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let json = serde_json::to_vec(&(&self.methods, &self.types))
+            .expect("methods and types always serialize to JSON");
+        let hash = json.iter().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+        });
+
+        format!("{hash:016x}")
+    }
+
+    pub fn identity(&self) -> ApiIdentity {
+        ApiIdentity {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            fingerprint: self.fingerprint(),
+        }
+    }
+
     fn get_type_refs_from_type(ty: &Type) -> Vec<&str> {
         match ty {
             Type::TypeRef(type_ref) => vec![type_ref.as_str()],
@@ -75,6 +117,8 @@ impl ApiSpec {
                     .iter()
                     .flat_map(Self::get_type_refs_from_type)
                     .chain(Self::get_type_refs_from_type(&m.returns))
+                    .chain(m.error.iter().flat_map(Self::get_type_refs_from_type))
+                    .chain(m.stream.iter().flat_map(Self::get_type_refs_from_type))
             })
             .collect();
 
@@ -101,6 +145,17 @@ impl ApiSpec {
 pub struct LiRpcMethodSpec {
     pub messages: Vec<Type>,
     pub returns: Type,
+    /// The type of the errors a method can respond with, for methods returning a `Result`.
+    /// In that case `returns` is the type of the `Ok` variant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Type>,
+    /// The type of the items a method streams back using the `OutputStream` extractor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<Type>,
+    /// Set for methods meant to be sent as notifications, which get no response,
+    /// see `ServerBuilder::with_notification_handlers`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub notification: bool,
 }
 
 #[cfg(test)]
@@ -125,6 +180,9 @@ mod tests {
                         Box::new(Type::I128),
                         Box::new(Type::TypeRef("Error".to_string())),
                     ),
+                    error: None,
+                    stream: None,
+                    notification: false,
                 },
             )]),
             BTreeMap::new(),
@@ -132,4 +190,32 @@ mod tests {
 
         assert!(api_spec.is_err());
     }
+
+    #[test]
+    fn should_fingerprint_methods_and_types_only() {
+        let spec = |version: &str, returns: Type| {
+            ApiSpec::new(
+                "myapp".to_string(),
+                version.to_string(),
+                BTreeMap::from([(
+                    "greet".to_string(),
+                    LiRpcMethodSpec {
+                        messages: vec![],
+                        returns,
+                        error: None,
+                        stream: None,
+                        notification: false,
+                    },
+                )]),
+                BTreeMap::new(),
+            )
+            .unwrap()
+        };
+
+        let fingerprint = spec("0.1.0", Type::String).fingerprint();
+
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprint, spec("0.2.0", Type::String).fingerprint());
+        assert_ne!(fingerprint, spec("0.1.0", Type::U32).fingerprint());
+    }
 }
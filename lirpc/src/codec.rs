@@ -0,0 +1,180 @@
+//! The wire formats messages can be encoded in. JSON is always available,
+//! MessagePack and CBOR behind the `msgpack` and `cbor` features.
+//!
+//! Clients pick the codec when connecting. WebSocket clients request the
+//! `lirpc.<name>` subprotocol, e.g. `lirpc.msgpack`. Length-delimited clients
+//! send a JSON handshake `{"codec": "<name>"}` as their very first frame, which
+//! the server answers with the codec it is going to use. Connections without
+//! either use JSON.
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Prefix of the WebSocket subprotocols used to negotiate the codec
+const SUBPROTOCOL_PREFIX: &str = "lirpc.";
+
+/// How messages are encoded on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("Error encoding message: {0}")]
+    Encode(String),
+    #[error("Error decoding message: {0}")]
+    Decode(String),
+}
+
+impl Codec {
+    /// The name the codec is negotiated with
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => "msgpack",
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => "cbor",
+        }
+    }
+
+    /// The codec with the given name, if it is supported
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Codec::Json),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Some(Codec::MessagePack),
+            #[cfg(feature = "cbor")]
+            "cbor" => Some(Codec::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Whether the encoded messages are binary, instead of text
+    pub fn is_binary(self) -> bool {
+        !matches!(self, Codec::Json)
+    }
+
+    pub fn encode<M: Serialize>(self, message: &M) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Codec::Json => {
+                serde_json::to_vec(message).map_err(|e| CodecError::Encode(e.to_string()))
+            }
+            // Named, as fields that are skipped when serializing would shift the fields of a tuple
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                rmp_serde::to_vec_named(message).map_err(|e| CodecError::Encode(e.to_string()))
+            }
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::into_writer(message, &mut encoded)
+                    .map_err(|e| CodecError::Encode(e.to_string()))?;
+
+                Ok(encoded)
+            }
+        }
+    }
+
+    pub fn decode<M: DeserializeOwned>(self, raw: &[u8]) -> Result<M, CodecError> {
+        match self {
+            Codec::Json => {
+                serde_json::from_slice(raw).map_err(|e| CodecError::Decode(e.to_string()))
+            }
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                rmp_serde::from_slice(raw).map_err(|e| CodecError::Decode(e.to_string()))
+            }
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                ciborium::from_reader(raw).map_err(|e| CodecError::Decode(e.to_string()))
+            }
+        }
+    }
+
+    /// Whether the encoded message is an array, e.g. a batch of requests
+    pub(crate) fn is_array(self, raw: &[u8]) -> bool {
+        match self {
+            Codec::Json => raw
+                .iter()
+                .find(|byte| !byte.is_ascii_whitespace())
+                .is_some_and(|byte| *byte == b'['),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                matches!(raw.first(), Some(0x90..=0x9f | 0xdc | 0xdd))
+            }
+            // Major type 4
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => matches!(raw.first(), Some(0x80..=0x9f)),
+        }
+    }
+
+    /// The codec of the first supported `lirpc.<name>` subprotocol
+    /// in the `Sec-WebSocket-Protocol` header of an upgrade request
+    pub(crate) fn from_subprotocols(header: &str) -> Option<Self> {
+        header
+            .split(',')
+            .filter_map(|protocol| protocol.trim().strip_prefix(SUBPROTOCOL_PREFIX))
+            .find_map(Codec::from_name)
+    }
+
+    pub(crate) fn subprotocol(self) -> String {
+        format!("{SUBPROTOCOL_PREFIX}{}", self.name())
+    }
+}
+
+/// The first frame of length-delimited connections that don't use JSON,
+/// and the answer of the server to it. Always encoded as JSON.
+#[derive(Debug, Deserialize)]
+pub(crate) struct CodecHandshake {
+    pub(crate) codec: String,
+}
+
+impl CodecHandshake {
+    /// Reads the handshake from the first frame of a connection, returns `None`
+    /// when the frame is a regular request instead
+    pub(crate) fn from_first_frame(raw: &[u8]) -> Option<Self> {
+        serde_json::from_slice(raw).ok()
+    }
+
+    /// The answer of the server, telling the client which codec is used from now on
+    pub(crate) fn answer(codec: Codec) -> Vec<u8> {
+        serde_json::json!({ "codec": codec.name() })
+            .to_string()
+            .into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{Codec, CodecHandshake};
+
+    #[test]
+    fn should_pick_first_supported_subprotocol() {
+        assert_eq!(
+            Codec::from_subprotocols("graphql-ws, lirpc.unknown, lirpc.json"),
+            Some(Codec::Json)
+        );
+        assert_eq!(Codec::from_subprotocols("graphql-ws"), None);
+    }
+
+    #[test]
+    fn should_recognize_arrays() {
+        assert!(Codec::Json.is_array(b" \n[{}]"));
+        assert!(!Codec::Json.is_array(br#"{"headers":{}}"#));
+        assert!(!Codec::Json.is_array(b""));
+    }
+
+    #[test]
+    fn should_not_take_requests_for_handshakes() {
+        let request = br#"{"headers":{"id":1,"function":"codec"},"payload":null}"#;
+
+        assert!(CodecHandshake::from_first_frame(request).is_none());
+        assert!(CodecHandshake::from_first_frame(br#"{"codec":"json"}"#).is_some());
+    }
+}
@@ -1,10 +1,48 @@
-#[derive(Default)]
+use std::net::SocketAddr;
+
+use tokio::sync::mpsc;
+
+use crate::{
+    extractors::{PeerIdentity, UpgradeHeaders},
+    lirpc_message::LiRpcResponse,
+};
+
 pub struct ConnectionDetails<S: Clone> {
     pub connection_state: S,
+    /// Not known for connections over e.g. a Unix domain socket
+    pub peer_addr: Option<SocketAddr>,
+    /// Set when the client authenticated itself with a certificate over mutual TLS
+    pub peer_identity: Option<PeerIdentity>,
+    /// Set for WebSocket connections
+    pub upgrade_headers: Option<UpgradeHeaders>,
+    /// Sender for the responses going out over this connection.
+    /// Used by extractors that send more than the single response of a method.
+    pub(crate) output: mpsc::Sender<LiRpcResponse>,
 }
 
 impl<S: Clone> ConnectionDetails<S> {
-    pub fn new(connection_state: S) -> Self {
-        Self { connection_state }
+    pub fn new(connection_state: S, output: mpsc::Sender<LiRpcResponse>) -> Self {
+        Self {
+            connection_state,
+            peer_addr: None,
+            peer_identity: None,
+            upgrade_headers: None,
+            output,
+        }
+    }
+
+    pub fn with_peer_addr(mut self, peer_addr: Option<SocketAddr>) -> Self {
+        self.peer_addr = peer_addr;
+        self
+    }
+
+    pub fn with_peer_identity(mut self, peer_identity: Option<PeerIdentity>) -> Self {
+        self.peer_identity = peer_identity;
+        self
+    }
+
+    pub fn with_upgrade_headers(mut self, upgrade_headers: Option<UpgradeHeaders>) -> Self {
+        self.upgrade_headers = upgrade_headers;
+        self
     }
 }
@@ -1,12 +1,16 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::sync::watch;
 
+use crate::translatable::{Translatable, Type};
+
 #[derive(Debug, thiserror::Error)]
 pub enum LiRpcError {
     #[error("Error deserializing: {0}")]
     DeserializeError(#[from] serde_json::Error),
     #[error("IoError: {0}")]
     IoError(#[from] std::io::Error),
-    #[error("Unable to parse websocket message type that is not `Text`")]
+    #[error("Unable to parse websocket message type that is neither `Text` nor `Binary`")]
     UnableToParseWebsocketMessage,
     #[error("Raw message could not be split into headers and payload")]
     RawMessageCouldNotBeSplitOnHeaderAndPayload,
@@ -22,4 +26,68 @@ pub enum LiRpcError {
     ErrorTurningHandlerErrorIntoRawLiRpcResponsePayload(String),
     #[error("Error turning extractor error into raw LiRpc response payload: {0}")]
     ErrorTurningExtractorErrorIntoRawLiRpcResponsePayload(String),
+    #[error("TLS error: {0}")]
+    TlsError(#[from] rustls::Error),
+    #[error("Error reading PEM: {0}")]
+    PemError(#[from] rustls::pki_types::pem::Error),
+    #[error("Error setting up client certificate verification: {0}")]
+    ClientCertVerifierError(#[from] rustls::server::VerifierBuilderError),
+}
+
+/// Errors on the protocol level, sent back to the client as the payload of
+/// a response with the `res: err` header.
+///
+/// Serialized as `{"error": "<kind>", "detail": <detail>}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+#[serde(rename_all = "snake_case", tag = "error", content = "detail")]
+pub enum LiRpcProtocolError {
+    /// No method is registered under the requested name
+    #[error("unknown method: {0}")]
+    UnknownMethod(String),
+    /// The request could not be parsed into the `headers` and `payload` envelope
+    #[error("malformed envelope: {0}")]
+    MalformedEnvelope(String),
+    /// The payload of the request does not match the message type of the method
+    #[error("malformed payload: {0}")]
+    MalformedPayload(String),
+    /// One of the method's extractors refused the request,
+    /// the detail contains the error returned by the extractor
+    #[error("extractor rejection: {0}")]
+    ExtractorRejection(Value),
+    /// The server ran into an error it was unable to recover from
+    #[error("server error: {0}")]
+    ServerError(String),
+    /// The method did not respond in time, see `ServerBuilder::with_request_timeout`
+    /// and the `timeout_ms` request header
+    #[error("timeout: {0}")]
+    Timeout(String),
+    /// The client is not compatible with the server, in response to the `lirpc_hello`
+    /// handshake. See `ServerBuilder::refuse_incompatible_clients`
+    #[error("incompatible: {0}")]
+    Incompatible(String),
+}
+
+impl LiRpcProtocolError {
+    /// The `error` tags of all protocol errors, as documented in the api spec
+    pub const KINDS: [&str; 7] = [
+        "unknown_method",
+        "malformed_envelope",
+        "malformed_payload",
+        "extractor_rejection",
+        "server_error",
+        "timeout",
+        "incompatible",
+    ];
+
+    pub(crate) fn server_error() -> Self {
+        Self::ServerError(
+            "an error occurred on the server which it was unable to recover from".to_string(),
+        )
+    }
+}
+
+impl Translatable for LiRpcProtocolError {
+    fn get_type() -> Type {
+        Type::TypeRef("LiRpcProtocolError".to_string())
+    }
 }
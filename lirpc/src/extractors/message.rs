@@ -2,7 +2,8 @@ use serde::Deserialize;
 
 use crate::{
     connection_details::ConnectionDetails,
-    extractors::{FromConnectionMessage, error::LiRpcExtractorError},
+    error::LiRpcProtocolError,
+    extractors::FromConnectionMessage,
     lirpc_message::{LiRpcPayload, LiRpcRequest},
     lirpc_type::LiRpcType,
     translatable::Type,
@@ -18,18 +19,26 @@ where
     C: Clone + Send + Sync + 'static,
     S: Clone + Send + Sync + 'static,
 {
-    type Error = LiRpcExtractorError;
+    type Error = LiRpcProtocolError;
 
     async fn from_connection_message(
         _connection: &ConnectionDetails<C>,
         message: &LiRpcRequest,
         _state: &S,
     ) -> Result<Self, Self::Error> {
-        match &message.payload {
-            Some(LiRpcPayload(json_value)) => Ok(Self(serde_json::from_value(json_value.clone())?)),
+        let parsed = match &message.payload {
+            Some(LiRpcPayload(json_value)) => serde_json::from_value(json_value.clone()),
             // TODO: probably not very clean to just parse an empty string here
-            None => Ok(Self(serde_json::from_str("")?)),
-        }
+            None => serde_json::from_str(""),
+        };
+
+        parsed
+            .map(Self)
+            .map_err(|e| LiRpcProtocolError::MalformedPayload(e.to_string()))
+    }
+
+    fn rejection(error: Self::Error) -> LiRpcProtocolError {
+        error
     }
 
     fn extends_signature_with() -> Option<Type> {
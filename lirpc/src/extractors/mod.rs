@@ -1,14 +1,22 @@
 mod connection_state;
-pub(super) mod error;
 mod message;
+mod output_stream;
+mod peer_addr;
+mod peer_identity;
 mod state;
+mod upgrade_headers;
 
 pub use connection_state::ConnectionState;
 pub use message::Message;
+pub use output_stream::OutputStream;
+pub use peer_addr::PeerAddr;
+pub use peer_identity::PeerIdentity;
 pub use state::State;
+pub use upgrade_headers::UpgradeHeaders;
 
 use crate::{
     connection_details::ConnectionDetails,
+    error::LiRpcProtocolError,
     lirpc_message::LiRpcRequest,
     translatable::{Translatable, Type},
 };
@@ -33,4 +41,21 @@ where
     fn extends_signature_with() -> Option<Type> {
         None
     }
+
+    /// Turns an error returned by this extractor into the
+    /// protocol error that is sent back to the client.
+    /// By default the error is sent as an `extractor_rejection`
+    fn rejection(error: Self::Error) -> LiRpcProtocolError {
+        match serde_json::to_value(error) {
+            Ok(detail) => LiRpcProtocolError::ExtractorRejection(detail),
+            Err(_) => LiRpcProtocolError::server_error(),
+        }
+    }
+
+    /// Extractors that make the method stream
+    /// its output should return the type of
+    /// the streamed items here
+    fn streams_with() -> Option<Type> {
+        None
+    }
 }
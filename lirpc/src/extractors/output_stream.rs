@@ -0,0 +1,127 @@
+use std::marker::PhantomData;
+
+use tokio::sync::mpsc;
+
+use crate::{
+    connection_details::ConnectionDetails,
+    error::LiRpcError,
+    extractors::FromConnectionMessage,
+    lirpc_message::{
+        LiRpcPayload, LiRpcRequest, LiRpcResponse, LiRpcResponseHeaders, LiRpcResponseResultHeader,
+        LiRpcStreamHeader,
+    },
+    translatable::{Translatable, Type},
+};
+
+/// Streams multiple messages back to the client for a single request.
+///
+/// Every item sent is delivered to the client under the id of the request
+/// that invoked the handler. The stream is ended by the server once the
+/// handler returns, items sent after that point won't reach the client.
+///
+/// # Example
+///
+/// ```rust
+/// # use lirpc::extractors::OutputStream;
+/// async fn count_to_ten(output: OutputStream<u32>) {
+///     for i in 1..=10 {
+///         if output.send(i).await.is_err() {
+///             // the connection was closed
+///             return;
+///         }
+///     }
+/// }
+/// ```
+pub struct OutputStream<T> {
+    message_id: u32,
+    sender: mpsc::Sender<LiRpcResponse>,
+    _item_type: PhantomData<fn(T)>,
+}
+
+impl<T> OutputStream<T>
+where
+    T: Translatable,
+{
+    pub async fn send(&self, item: T) -> Result<(), LiRpcError> {
+        let payload = serde_json::to_value(item)?;
+
+        let response = LiRpcResponse::new(
+            LiRpcResponseHeaders::new(self.message_id, LiRpcResponseResultHeader::Ok)
+                .with_stream(LiRpcStreamHeader::Item),
+            Some(LiRpcPayload::new(payload)),
+        );
+
+        self.sender
+            .send(response)
+            .await
+            .map_err(|_| LiRpcError::OutputStreamClosed)
+    }
+}
+
+impl<S, C, T> FromConnectionMessage<S, C> for OutputStream<T>
+where
+    T: Translatable + Send + 'static,
+    C: Clone + Send + Sync + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Error = ();
+
+    async fn from_connection_message(
+        connection: &ConnectionDetails<C>,
+        message: &LiRpcRequest,
+        _state: &S,
+    ) -> Result<Self, Self::Error> {
+        // Nothing is sent back for notifications, so the stream is closed from the start
+        let sender = match message.headers.notification {
+            true => mpsc::channel(1).0,
+            false => connection.output.clone(),
+        };
+
+        Ok(Self {
+            message_id: message.headers.id,
+            sender,
+            _item_type: PhantomData,
+        })
+    }
+
+    fn streams_with() -> Option<Type> {
+        Some(T::get_type())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        connection_details::ConnectionDetails,
+        extractors::{FromConnectionMessage, OutputStream},
+        lirpc_message::LiRpcRequest,
+    };
+
+    #[tokio::test]
+    async fn should_send_items_under_request_id() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let connection = ConnectionDetails::new((), tx);
+        let request: LiRpcRequest =
+            serde_json::from_value(json!({"headers": {"id": 7, "function": "count"}})).unwrap();
+
+        let output = <OutputStream<u32> as FromConnectionMessage<(), ()>>::from_connection_message(
+            &connection,
+            &request,
+            &(),
+        )
+        .await
+        .unwrap();
+
+        output.send(1).await.unwrap();
+
+        let response = rx.recv().await.unwrap();
+
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            json!({"headers": {"id": 7, "stream": "item"}, "payload": 1})
+        );
+    }
+}
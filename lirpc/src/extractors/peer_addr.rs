@@ -0,0 +1,41 @@
+use std::net::SocketAddr;
+
+use crate::{
+    connection_details::ConnectionDetails, extractors::FromConnectionMessage,
+    lirpc_message::LiRpcRequest,
+};
+
+/// The address of the client on the other end of the connection.
+///
+/// Rejects requests on connections without a peer address,
+/// like those over a Unix domain socket or in memory.
+///
+/// # Example
+/// ```rust
+/// use lirpc::extractors::PeerAddr;
+///
+/// async fn my_address(PeerAddr(addr): PeerAddr) -> String {
+///     addr.to_string()
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerAddr(pub SocketAddr);
+
+impl<S, C> FromConnectionMessage<S, C> for PeerAddr
+where
+    C: Clone + Send + Sync + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Error = String;
+
+    async fn from_connection_message(
+        connection: &ConnectionDetails<C>,
+        _message: &LiRpcRequest,
+        _state: &S,
+    ) -> Result<Self, Self::Error> {
+        connection
+            .peer_addr
+            .map(Self)
+            .ok_or_else(|| "the address of the client is not known".to_string())
+    }
+}
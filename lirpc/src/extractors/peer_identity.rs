@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use rustls::pki_types::CertificateDer;
+
+use crate::{
+    connection_details::ConnectionDetails, extractors::FromConnectionMessage,
+    lirpc_message::LiRpcRequest,
+};
+
+/// The identity of a client that authenticated itself with a certificate over mutual TLS,
+/// see `ServerBuilder::with_mutual_tls_pem_files`.
+///
+/// As an extractor it rejects requests on connections without a verified client certificate.
+///
+/// # Example
+/// ```rust
+/// use lirpc::extractors::PeerIdentity;
+///
+/// async fn whoami(identity: PeerIdentity) -> usize {
+///     identity.certificate().len()
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerIdentity {
+    certificates: Arc<[CertificateDer<'static>]>,
+}
+
+impl PeerIdentity {
+    /// Returns `None` for an empty chain, as it doesn't identify anyone
+    pub(crate) fn from_certificates(certificates: &[CertificateDer<'static>]) -> Option<Self> {
+        if certificates.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            certificates: certificates.into(),
+        })
+    }
+
+    /// The (DER encoded) certificate of the client itself
+    pub fn certificate(&self) -> &CertificateDer<'static> {
+        &self.certificates[0]
+    }
+
+    /// The full certificate chain the client presented, starting with its own certificate
+    pub fn certificate_chain(&self) -> &[CertificateDer<'static>] {
+        &self.certificates
+    }
+}
+
+impl<S, C> FromConnectionMessage<S, C> for PeerIdentity
+where
+    C: Clone + Send + Sync + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Error = String;
+
+    async fn from_connection_message(
+        connection: &ConnectionDetails<C>,
+        _message: &LiRpcRequest,
+        _state: &S,
+    ) -> Result<Self, Self::Error> {
+        connection
+            .peer_identity
+            .clone()
+            .ok_or_else(|| "the client did not present a certificate".to_string())
+    }
+}
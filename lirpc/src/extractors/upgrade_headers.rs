@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use tokio_tungstenite::tungstenite::http::{HeaderMap, Uri};
+
+use crate::{
+    connection_details::ConnectionDetails, extractors::FromConnectionMessage,
+    lirpc_message::LiRpcRequest,
+};
+
+/// The URI and headers of the HTTP request a WebSocket connection was upgraded from.
+/// Browsers can't set headers on WebSocket messages, so this is where e.g. cookies,
+/// the `Authorization` header, query parameters and the `Origin` of browser clients are.
+///
+/// As an extractor it rejects requests on connections that are not WebSocket connections.
+///
+/// # Example
+/// ```rust
+/// use lirpc::extractors::UpgradeHeaders;
+///
+/// async fn has_session(upgrade: UpgradeHeaders) -> bool {
+///     upgrade.headers().contains_key("cookie")
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct UpgradeHeaders {
+    request: Arc<(Uri, HeaderMap)>,
+}
+
+impl UpgradeHeaders {
+    pub(crate) fn new(uri: Uri, headers: HeaderMap) -> Self {
+        Self {
+            request: Arc::new((uri, headers)),
+        }
+    }
+
+    /// The requested URI, including the path and query
+    pub fn uri(&self) -> &Uri {
+        &self.request.0
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.request.1
+    }
+}
+
+impl<S, C> FromConnectionMessage<S, C> for UpgradeHeaders
+where
+    C: Clone + Send + Sync + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Error = String;
+
+    async fn from_connection_message(
+        connection: &ConnectionDetails<C>,
+        _message: &LiRpcRequest,
+        _state: &S,
+    ) -> Result<Self, Self::Error> {
+        connection
+            .upgrade_headers
+            .clone()
+            .ok_or_else(|| "the connection is not a WebSocket connection".to_string())
+    }
+}
@@ -1,16 +1,12 @@
 use std::{future::Future, pin::Pin, sync::Arc};
 
-use serde_json::json;
-use tracing::error;
-
 use crate::{
     api_spec::LiRpcMethodSpec,
     connection_details::ConnectionDetails,
     extractors::FromConnectionMessage,
-    lirpc_message::{
-        LiRpcPayload, LiRpcRequest, LiRpcResponse, LiRpcResponseHeaders, LiRpcResponseResultHeader,
-    },
-    translatable::{Translatable, Type},
+    into_lirpc_response::IntoLiRpcResponse,
+    lirpc_message::{LiRpcRequest, LiRpcResponse},
+    translatable::Type,
 };
 
 pub trait Handler<F, T, S, C, R>
@@ -28,38 +24,8 @@ where
     fn get_spec(&self) -> LiRpcMethodSpec;
 }
 
-fn build_lirpc_response(message_id: u32, is_ok: bool, payload: impl Translatable) -> LiRpcResponse {
-    let serialized_payload = serde_json::to_value(payload);
-
-    let headers = LiRpcResponseHeaders::new(
-        message_id,
-        match (is_ok, &serialized_payload) {
-            // An error occurred in the handler
-            (false, _) => LiRpcResponseResultHeader::Err,
-            // An error occurred when serializing the response
-            (true, Err(_)) => LiRpcResponseResultHeader::Err,
-            // both things went Ok
-            (true, Ok(_)) => LiRpcResponseResultHeader::Ok,
-        },
-    );
-
-    LiRpcResponse::new(
-        headers,
-        Some(LiRpcPayload::new(match serialized_payload {
-            Ok(p) => p,
-            Err(e) => {
-                error!("error serializing response: {e}");
-                json!({
-                    "error": "server_error",
-                    "detail": "an error occurred on the server which it was unable to recover from"
-                })
-            }
-        })),
-    )
-}
-
 macro_rules! try_extract {
-    ($Ty:ty, $connection:expr, $message:expr, $state:expr) => {{
+    ($Ty:ty, $connection:expr, $message:expr, $state:expr, $ends_stream:expr) => {{
         match <$Ty as FromConnectionMessage<_, _>>::from_connection_message(
             &$connection,
             &$message,
@@ -69,10 +35,11 @@ macro_rules! try_extract {
         {
             Ok(value) => value,
             Err(e) => {
-                // Format the error into a Send-safe String outside the async block,
-                // then early-return a pinned async error future.
-                // return Err(LiRpcError::ExtractorError(e));
-                return build_lirpc_response($message.headers.id, false, e);
+                return LiRpcResponse::from_protocol_error(
+                    $message.headers.id,
+                    <$Ty as FromConnectionMessage<_, _>>::rejection(e),
+                )
+                .ending_stream($ends_stream);
             }
         }
     }};
@@ -86,7 +53,7 @@ macro_rules! impl_handler {
             F: Fn( $($Ti),* ) -> Fut + Send + Sync + 'static,
             C: Clone + Send + Sync + 'static,
             Fut: Future<Output = R> + Send + 'static,
-            R: Translatable,
+            R: IntoLiRpcResponse,
             S: Clone + Send + Sync + 'static,
             $( $Ti: FromConnectionMessage<S, C>, )*
         {
@@ -100,20 +67,28 @@ macro_rules! impl_handler {
                 let _ = (&connection, &message, &state);
 
                 let slf = self.clone();
+                let ends_stream = [$($Ti::streams_with().is_some(),)*].contains(&true);
 
                 Box::pin(async move {
-                    build_lirpc_response(message.headers.id, true, slf($(
-                        try_extract!($Ti, connection, message, state)
-                    ),*).await)
+                    slf($(
+                        try_extract!($Ti, connection, message, state, ends_stream)
+                    ),*)
+                    .await
+                    .into_lirpc_response(message.headers.id)
+                    .ending_stream(ends_stream)
                 })
             }
 
             fn get_spec(&self) -> LiRpcMethodSpec {
                 let signature_extensions: Vec<Option<Type>> = vec![$($Ti::extends_signature_with(),)*];
+                let stream_types: Vec<Option<Type>> = vec![$($Ti::streams_with(),)*];
 
                 LiRpcMethodSpec {
                     messages: signature_extensions.into_iter().flatten().collect(),
-                    returns: R::get_type(),
+                    returns: R::ok_type(),
+                    error: R::err_type(),
+                    stream: stream_types.into_iter().flatten().next(),
+                    notification: false,
                 }
             }
 
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    error::LiRpcProtocolError,
+    lirpc_message::{LiRpcPayload, LiRpcResponse, LiRpcResponseHeaders, LiRpcResponseResultHeader},
+    translatable::{Translatable, Type},
+};
+
+/// Turns the value returned by a handler into the response sent to the client.
+///
+/// Values are sent as a successful response, except for the `Err` variant
+/// of a `Result`, which is sent with the `res: err` header.
+///
+/// Implemented for the basic types and for types deriving `LiRpcType`.
+pub trait IntoLiRpcResponse {
+    fn into_lirpc_response(self, message_id: u32) -> LiRpcResponse;
+
+    /// The type of the payload of a successful response
+    fn ok_type() -> Type;
+
+    /// The type of the payload of a response with the `res: err` header,
+    /// for return types that can fail
+    fn err_type() -> Option<Type> {
+        None
+    }
+}
+
+/// Builds the response for the result of a method. If the payload
+/// can't be serialized, a `server_error` is sent instead.
+pub fn build_lirpc_response<T, E>(message_id: u32, result: Result<T, E>) -> LiRpcResponse
+where
+    T: Serialize,
+    E: Serialize,
+{
+    let (res, serialized_payload) = match result {
+        Ok(payload) => (LiRpcResponseResultHeader::Ok, serde_json::to_value(payload)),
+        Err(payload) => (
+            LiRpcResponseResultHeader::Err,
+            serde_json::to_value(payload),
+        ),
+    };
+
+    match serialized_payload {
+        Ok(payload) => LiRpcResponse::new(
+            LiRpcResponseHeaders::new(message_id, res),
+            Some(LiRpcPayload::new(payload)),
+        ),
+        Err(e) => {
+            error!("error serializing response: {e}");
+            LiRpcResponse::from_protocol_error(message_id, LiRpcProtocolError::server_error())
+        }
+    }
+}
+
+macro_rules! impl_basic_into_lirpc_response {
+    ($Ti:ty) => {
+        impl IntoLiRpcResponse for $Ti {
+            fn into_lirpc_response(self, message_id: u32) -> LiRpcResponse {
+                build_lirpc_response::<_, ()>(message_id, Ok(self))
+            }
+
+            fn ok_type() -> Type {
+                <$Ti as Translatable>::get_type()
+            }
+        }
+    };
+}
+
+impl_basic_into_lirpc_response!(i8);
+impl_basic_into_lirpc_response!(i16);
+impl_basic_into_lirpc_response!(i32);
+impl_basic_into_lirpc_response!(i64);
+impl_basic_into_lirpc_response!(i128);
+impl_basic_into_lirpc_response!(u8);
+impl_basic_into_lirpc_response!(u16);
+impl_basic_into_lirpc_response!(u32);
+impl_basic_into_lirpc_response!(u64);
+impl_basic_into_lirpc_response!(u128);
+impl_basic_into_lirpc_response!(bool);
+impl_basic_into_lirpc_response!(String);
+impl_basic_into_lirpc_response!(());
+
+impl<T: Translatable> IntoLiRpcResponse for Box<T> {
+    fn into_lirpc_response(self, message_id: u32) -> LiRpcResponse {
+        build_lirpc_response::<_, ()>(message_id, Ok(self))
+    }
+
+    fn ok_type() -> Type {
+        <Self as Translatable>::get_type()
+    }
+}
+
+impl<T: Translatable> IntoLiRpcResponse for Option<T> {
+    fn into_lirpc_response(self, message_id: u32) -> LiRpcResponse {
+        build_lirpc_response::<_, ()>(message_id, Ok(self))
+    }
+
+    fn ok_type() -> Type {
+        <Self as Translatable>::get_type()
+    }
+}
+
+impl<T: Translatable> IntoLiRpcResponse for Vec<T> {
+    fn into_lirpc_response(self, message_id: u32) -> LiRpcResponse {
+        build_lirpc_response::<_, ()>(message_id, Ok(self))
+    }
+
+    fn ok_type() -> Type {
+        <Self as Translatable>::get_type()
+    }
+}
+
+impl<T: Translatable, E: Translatable> IntoLiRpcResponse for Result<T, E> {
+    fn into_lirpc_response(self, message_id: u32) -> LiRpcResponse {
+        build_lirpc_response(message_id, self)
+    }
+
+    fn ok_type() -> Type {
+        T::get_type()
+    }
+
+    fn err_type() -> Option<Type> {
+        Some(E::get_type())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::into_lirpc_response::IntoLiRpcResponse;
+
+    #[test]
+    fn should_send_err_with_err_header() {
+        let response = Err::<u32, String>("nope".to_string()).into_lirpc_response(1);
+
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            json!({"headers": {"id": 1, "res": "err"}, "payload": "nope"})
+        );
+    }
+
+    #[test]
+    fn should_send_ok_without_wrapping() {
+        let response = Ok::<u32, String>(5).into_lirpc_response(1);
+
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            json!({"headers": {"id": 1}, "payload": 5})
+        );
+    }
+}
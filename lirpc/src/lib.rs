@@ -5,14 +5,24 @@ mod server;
 mod service;
 
 pub mod api_spec;
+pub mod codec;
 pub mod codegen;
 pub mod error;
 pub mod extractors;
+pub mod into_lirpc_response;
+pub mod lifecycle;
 pub mod lirpc_message;
 pub mod lirpc_type;
+pub mod middleware;
 pub mod translatable;
 pub mod type_definition;
 
+pub use rustls;
+pub use tokio_tungstenite::tungstenite::http;
+
 pub use connection_details::ConnectionDetails;
+pub use server::ClientAuth;
+#[cfg(any(test, feature = "in-memory"))]
+pub use server::InMemoryClient;
 pub use server::NamedHandler;
 pub use server::ServerBuilder;
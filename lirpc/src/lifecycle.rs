@@ -0,0 +1,109 @@
+//! Hooks into the lifecycle of connections, see `ServerBuilder::on_connect`
+//! and `ServerBuilder::on_disconnect`.
+
+use std::net::SocketAddr;
+
+use futures::future::BoxFuture;
+
+use crate::extractors::{PeerIdentity, UpgradeHeaders};
+
+/// How the client talks to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionKind {
+    /// Length-delimited frames, over TCP, a Unix domain socket or in memory
+    Tcp,
+    WebSocket,
+}
+
+/// What is known about a connection once it is established, given to the `on_connect` hook
+#[derive(Debug, Clone)]
+pub struct HandshakeInfo {
+    pub kind: ConnectionKind,
+    /// Set when the client authenticated itself with a certificate over mutual TLS
+    pub peer_identity: Option<PeerIdentity>,
+    /// The HTTP request of WebSocket connections
+    pub upgrade_headers: Option<UpgradeHeaders>,
+}
+
+/// Returned by the `on_connect` hook to refuse a connection. The connection is closed
+/// right away, WebSocket clients receive the `reason` in the close frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reject {
+    pub reason: String,
+}
+
+impl Reject {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Why a connection was closed, given to the `on_disconnect` hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client closed the connection
+    Closed,
+    /// The server closed the connection because it is shutting down
+    Shutdown,
+    /// The server closed the connection because the client didn't send anything
+    /// for too long, see `ServerBuilder::with_read_idle_timeout`
+    Idle,
+    /// The server closed the connection because the client stopped answering
+    /// the heartbeat, see `ServerBuilder::with_heartbeat`
+    Unresponsive,
+    /// The server refused the client in the `lirpc_hello` handshake,
+    /// see `ServerBuilder::refuse_incompatible_clients`
+    Incompatible,
+    /// The connection broke down because of an error
+    Error(String),
+}
+
+pub(crate) type OnConnect<C> = Box<
+    dyn for<'a> Fn(
+            Option<SocketAddr>,
+            &'a mut C,
+            &'a HandshakeInfo,
+        ) -> BoxFuture<'a, Result<(), Reject>>
+        + Send
+        + Sync,
+>;
+
+pub(crate) type OnDisconnect<C> =
+    Box<dyn for<'a> Fn(&'a C, DisconnectReason) -> BoxFuture<'a, ()> + Send + Sync>;
+
+/// The lifecycle hooks registered on the server
+pub(crate) struct ConnectionHooks<C> {
+    pub(crate) on_connect: Option<OnConnect<C>>,
+    pub(crate) on_disconnect: Option<OnDisconnect<C>>,
+}
+
+impl<C> Default for ConnectionHooks<C> {
+    fn default() -> Self {
+        Self {
+            on_connect: None,
+            on_disconnect: None,
+        }
+    }
+}
+
+impl<C> ConnectionHooks<C> {
+    pub(crate) async fn connect(
+        &self,
+        peer_addr: Option<SocketAddr>,
+        connection_state: &mut C,
+        handshake: &HandshakeInfo,
+    ) -> Result<(), Reject> {
+        match &self.on_connect {
+            Some(on_connect) => on_connect(peer_addr, connection_state, handshake).await,
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn disconnect(&self, connection_state: &C, reason: DisconnectReason) {
+        if let Some(on_disconnect) = &self.on_disconnect {
+            on_disconnect(connection_state, reason).await
+        }
+    }
+}
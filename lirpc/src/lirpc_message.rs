@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
 
-use crate::error::LiRpcError;
+use crate::{codec::Codec, error::LiRpcProtocolError};
+
+/// The version of the protocol spoken by the server, exchanged by the `lirpc_hello` handshake
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Debug, Deserialize)]
 pub struct LiRpcRequest {
@@ -10,10 +12,84 @@ pub struct LiRpcRequest {
     pub payload: Option<LiRpcPayload>,
 }
 
+impl LiRpcRequest {
+    /// Parses a request from its raw JSON representation.
+    ///
+    /// # Error
+    /// When the request can't be parsed, the id of the request is
+    /// still recovered if possible, so the error can be replied to.
+    pub fn from_slice(raw: &[u8]) -> Result<Self, (Option<u32>, LiRpcProtocolError)> {
+        Self::decode(Codec::Json, raw)
+    }
+
+    /// Like `from_slice`, for requests encoded with `codec`
+    pub fn decode(codec: Codec, raw: &[u8]) -> Result<Self, (Option<u32>, LiRpcProtocolError)> {
+        codec.decode(raw).map_err(|e| {
+            (
+                Self::recover_id(codec, raw),
+                LiRpcProtocolError::MalformedEnvelope(e.to_string()),
+            )
+        })
+    }
+
+    /// Parses the requests in a frame, which holds either a single request or an array
+    /// of them (a batch). Every request of a batch is parsed on its own, so one malformed
+    /// request doesn't fail the others. An empty batch has no id to respond to, so it's only logged.
+    pub fn decode_frame(
+        codec: Codec,
+        raw: &[u8],
+    ) -> Vec<Result<Self, (Option<u32>, LiRpcProtocolError)>> {
+        if !codec.is_array(raw) {
+            return vec![Self::decode(codec, raw)];
+        }
+
+        match codec.decode::<Vec<Value>>(raw) {
+            Ok(batch) if batch.is_empty() => vec![Err((
+                None,
+                LiRpcProtocolError::MalformedEnvelope("the batch is empty".to_string()),
+            ))],
+            Ok(batch) => batch.into_iter().map(Self::from_value).collect(),
+            Err(e) => vec![Err((
+                None,
+                LiRpcProtocolError::MalformedEnvelope(e.to_string()),
+            ))],
+        }
+    }
+
+    fn from_value(value: Value) -> Result<Self, (Option<u32>, LiRpcProtocolError)> {
+        let id = Self::id_of(&value);
+
+        serde_json::from_value(value)
+            .map_err(|e| (id, LiRpcProtocolError::MalformedEnvelope(e.to_string())))
+    }
+
+    fn recover_id(codec: Codec, raw: &[u8]) -> Option<u32> {
+        Self::id_of(&codec.decode(raw).ok()?)
+    }
+
+    /// The id to report an error to, notifications never get a response
+    fn id_of(value: &Value) -> Option<u32> {
+        let headers = value.get("headers")?;
+        if headers.get("notification").and_then(Value::as_bool) == Some(true) {
+            return None;
+        }
+        let id = headers.get("id")?.as_u64()?;
+
+        u32::try_from(id).ok()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct LiRpcRequestHeaders {
     pub id: u32,
     pub function: String,
+    /// How long (in milliseconds) the client is willing to wait for the response.
+    /// The server gives up on the request once it passes.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Set for notifications: the method is run, but nothing is sent back
+    #[serde(default)]
+    pub notification: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -27,6 +103,25 @@ impl LiRpcResponse {
     pub fn new(headers: LiRpcResponseHeaders, payload: Option<LiRpcPayload>) -> Self {
         Self { headers, payload }
     }
+
+    pub fn from_protocol_error(id: u32, error: LiRpcProtocolError) -> Self {
+        let mut headers = LiRpcResponseHeaders::new(id, LiRpcResponseResultHeader::Err);
+        headers.protocol_error = true;
+
+        Self::new(
+            headers,
+            serde_json::to_value(error).ok().map(LiRpcPayload::new),
+        )
+    }
+
+    /// Marks the response as the end of a stream, when `ends_stream` is true
+    pub(crate) fn ending_stream(mut self, ends_stream: bool) -> Self {
+        if ends_stream {
+            self.headers = self.headers.with_stream(LiRpcStreamHeader::End);
+        }
+
+        self
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -34,6 +129,15 @@ pub struct LiRpcResponseHeaders {
     pub id: u32,
     #[serde(skip_serializing_if = "LiRpcResponseResultHeader::is_ok")]
     pub res: LiRpcResponseResultHeader,
+    /// Set on error responses with a `LiRpcProtocolError` as payload, to
+    /// tell them apart from errors returned by the method itself.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub protocol_error: bool,
+    /// Only set on responses to methods that stream their output.
+    /// Every streamed item is marked with `item`, the final response
+    /// of the method (its return value) is marked with `end`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<LiRpcStreamHeader>,
 }
 
 #[derive(Debug, Serialize)]
@@ -49,9 +153,26 @@ impl LiRpcResponseResultHeader {
     }
 }
 
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LiRpcStreamHeader {
+    Item,
+    End,
+}
+
 impl LiRpcResponseHeaders {
     pub fn new(id: u32, res: LiRpcResponseResultHeader) -> Self {
-        Self { id, res }
+        Self {
+            id,
+            res,
+            protocol_error: false,
+            stream: None,
+        }
+    }
+
+    pub fn with_stream(mut self, stream: LiRpcStreamHeader) -> Self {
+        self.stream = Some(stream);
+        self
     }
 }
 
@@ -64,23 +185,37 @@ impl LiRpcPayload {
     }
 }
 
-impl TryFrom<Message> for LiRpcRequest {
-    type Error = LiRpcError;
+#[cfg(test)]
+mod tests {
+    use crate::{codec::Codec, error::LiRpcProtocolError, lirpc_message::LiRpcRequest};
 
-    fn try_from(value: Message) -> Result<Self, Self::Error> {
-        match value {
-            Message::Text(raw_txt) => Ok(serde_json::from_str(&raw_txt)?),
-            _ => Err(LiRpcError::UnableToParseWebsocketMessage),
-        }
+    #[test]
+    fn should_recover_id_of_malformed_request() {
+        let result = LiRpcRequest::from_slice(br#"{"headers":{"id":3}}"#);
+
+        assert!(matches!(
+            result,
+            Err((Some(3), LiRpcProtocolError::MalformedEnvelope(_)))
+        ));
     }
-}
 
-impl TryFrom<LiRpcResponse> for Message {
-    type Error = LiRpcError;
+    #[test]
+    fn should_reject_empty_batch() {
+        let result = LiRpcRequest::decode_frame(Codec::Json, b"[]");
+
+        assert!(matches!(
+            result.as_slice(),
+            [Err((None, LiRpcProtocolError::MalformedEnvelope(_)))]
+        ));
+    }
 
-    fn try_from(so: LiRpcResponse) -> Result<Self, Self::Error> {
-        let response = serde_json::to_string(&so)?;
+    #[test]
+    fn should_not_recover_id_of_invalid_json() {
+        let result = LiRpcRequest::from_slice(br#"{"headers":{"id":3"#);
 
-        Ok(Message::Text(Utf8Bytes::from(response)))
+        assert!(matches!(
+            result,
+            Err((None, LiRpcProtocolError::MalformedEnvelope(_)))
+        ));
     }
 }
@@ -28,7 +28,7 @@
 ///
 /// // Note that the fields "name" and "version" are pulled
 /// // from the env variables that cargo sets.
-/// assert_eq!(api_spec, "{\"name\":\"lirpc\",\"version\":\"0.1.0\",\"methods\":{\"greet\":{\"messages\":[],\"returns\":\"unit\"}},\"types\":{\"GreetingRequest\":{\"struct\":{\"ident\":\"GreetingRequest\",\"fields\":{\"unnamed\":[]},\"generics\":[]}},\"GreetingResponse\":{\"struct\":{\"ident\":\"GreetingResponse\",\"fields\":{\"unnamed\":[]},\"generics\":[]}}}}");
+/// assert_eq!(api_spec, "{\"name\":\"lirpc\",\"version\":\"0.1.0\",\"methods\":{\"greet\":{\"messages\":[],\"returns\":\"unit\"}},\"types\":{\"GreetingRequest\":{\"struct\":{\"ident\":\"GreetingRequest\",\"fields\":{\"unnamed\":[]},\"generics\":[]}},\"GreetingResponse\":{\"struct\":{\"ident\":\"GreetingResponse\",\"fields\":{\"unnamed\":[]},\"generics\":[]}}},\"protocol_errors\":[\"unknown_method\",\"malformed_envelope\",\"malformed_payload\",\"extractor_rejection\",\"server_error\",\"timeout\",\"incompatible\"]}");
 /// ```
 #[macro_export]
 macro_rules! compile_json_api_spec {
@@ -0,0 +1,152 @@
+//! Middleware runs around the invocation of handlers, for concerns shared by
+//! multiple handlers like logging, authentication or timing.
+//! See `ServerBuilder::layer` and `ServerBuilder::with_handler_group`.
+
+use std::{pin::Pin, sync::Arc};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    api_spec::LiRpcMethodSpec,
+    connection_details::ConnectionDetails,
+    lirpc_message::{LiRpcRequest, LiRpcResponse},
+    service::Service,
+};
+
+/// Wraps the invocation of handlers. A middleware either passes the request on to
+/// the handler (or the next middleware) through `next`, or short-circuits by
+/// responding itself.
+///
+/// Implemented for async closures taking the same arguments as `handle`.
+///
+/// # Example
+/// ```rust
+/// use std::sync::Arc;
+///
+/// use lirpc::{
+///     ConnectionDetails,
+///     error::LiRpcProtocolError,
+///     lirpc_message::{LiRpcRequest, LiRpcResponse},
+///     middleware::Next,
+///     ServerBuilder,
+/// };
+///
+/// async fn require_identity(
+///     connection: Arc<ConnectionDetails<()>>,
+///     request: LiRpcRequest,
+///     state: (),
+///     next: Next<(), ()>,
+/// ) -> LiRpcResponse {
+///     if connection.peer_identity.is_none() {
+///         return LiRpcResponse::from_protocol_error(
+///             request.headers.id,
+///             LiRpcProtocolError::ExtractorRejection("unauthenticated".into()),
+///         );
+///     }
+///
+///     next.run(connection, request, state).await
+/// }
+///
+/// let server = ServerBuilder::new().layer(require_identity).build();
+/// ```
+pub trait Middleware<S, C>: Send + Sync + 'static
+where
+    C: Clone,
+{
+    fn handle(
+        &self,
+        connection: Arc<ConnectionDetails<C>>,
+        request: LiRpcRequest,
+        state: S,
+        next: Next<S, C>,
+    ) -> BoxFuture<'static, LiRpcResponse>;
+}
+
+impl<S, C, F, Fut> Middleware<S, C> for F
+where
+    F: Fn(Arc<ConnectionDetails<C>>, LiRpcRequest, S, Next<S, C>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = LiRpcResponse> + Send + 'static,
+    C: Clone,
+{
+    fn handle(
+        &self,
+        connection: Arc<ConnectionDetails<C>>,
+        request: LiRpcRequest,
+        state: S,
+        next: Next<S, C>,
+    ) -> BoxFuture<'static, LiRpcResponse> {
+        Box::pin(self(connection, request, state, next))
+    }
+}
+
+/// The rest of the chain a middleware wraps around, ending with the handler
+pub struct Next<S, C>
+where
+    C: Clone,
+{
+    service: Arc<dyn Service<S, C>>,
+}
+
+impl<S, C> Next<S, C>
+where
+    S: Send + Sync + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    /// Passes the request on to the next middleware, or the handler itself
+    pub async fn run(
+        self,
+        connection: Arc<ConnectionDetails<C>>,
+        request: LiRpcRequest,
+        state: S,
+    ) -> LiRpcResponse {
+        self.service.call(connection, request, state).await
+    }
+}
+
+/// A service (i.e. a handler) wrapped by a middleware
+pub(crate) struct MiddlewareService<S, C>
+where
+    C: Clone,
+{
+    middleware: Arc<dyn Middleware<S, C>>,
+    service: Arc<dyn Service<S, C>>,
+}
+
+impl<S, C> MiddlewareService<S, C>
+where
+    S: Send + Sync + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    pub(crate) fn wrap(
+        middleware: Arc<dyn Middleware<S, C>>,
+        service: Box<dyn Service<S, C>>,
+    ) -> Box<dyn Service<S, C>> {
+        Box::new(Self {
+            middleware,
+            service: Arc::from(service),
+        })
+    }
+}
+
+impl<S, C> Service<S, C> for MiddlewareService<S, C>
+where
+    S: Send + Sync + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    fn call(
+        &self,
+        connection: Arc<ConnectionDetails<C>>,
+        message: LiRpcRequest,
+        state: S,
+    ) -> Pin<Box<dyn Future<Output = LiRpcResponse> + Send>> {
+        let next = Next {
+            service: self.service.clone(),
+        };
+
+        self.middleware.handle(connection, message, state, next)
+    }
+
+    fn get_spec(&self) -> LiRpcMethodSpec {
+        self.service.get_spec()
+    }
+}
@@ -1,33 +1,94 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    any::Any,
+    collections::{BTreeMap, HashMap, HashSet},
+    future::{self, Future},
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
     sync::Arc,
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    fs, io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
 };
 
-use bytes::Bytes;
-use futures::{SinkExt, StreamExt};
+use bytes::{Bytes, BytesMut};
+use futures::{FutureExt, SinkExt, StreamExt, future::BoxFuture};
+use rustls::{
+    RootCertStore, ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::{
-    net::{TcpListener, TcpStream, ToSocketAddrs},
-    sync::mpsc,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    net::{TcpListener, ToSocketAddrs},
+    sync::{Semaphore, mpsc},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{
+    accept_hdr_async_with_config,
+    tungstenite::{
+        Message,
+        handshake::server::{Request, Response},
+        http::{HeaderValue, StatusCode, header::SEC_WEBSOCKET_PROTOCOL},
+        protocol::{CloseFrame, WebSocketConfig, frame::coding::CloseCode},
+    },
 };
-use tokio_tungstenite::accept_async;
-use tokio_util::codec::LengthDelimitedCodec;
-use tracing::{debug, error, warn};
+use tokio_util::{codec::LengthDelimitedCodec, sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, error, info, warn};
 
-/// Maximum size (in bytes) of a single length-prefixed TCP frame, guarding
-/// against a malformed/oversized length prefix causing an unbounded allocation.
-const MAX_TCP_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+/// Default maximum size (in bytes) of a single message, guarding against a
+/// malformed/oversized length prefix causing an unbounded allocation.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Prefix of the methods reserved by the server, handlers should be named differently
+const RESERVED_PREFIX: &str = "lirpc_";
 
 use crate::{
-    api_spec::ApiSpec,
+    api_spec::{ApiIdentity, ApiSpec},
+    codec::{Codec, CodecError, CodecHandshake},
     connection_details::ConnectionDetails,
-    error::LiRpcError,
+    error::{LiRpcError, LiRpcProtocolError},
+    extractors::{PeerIdentity, UpgradeHeaders},
     handler::Handler,
-    lirpc_message::{LiRpcRequest, LiRpcResponse},
-    service::{HandlerService, Service},
-    translatable::Translatable,
+    into_lirpc_response::IntoLiRpcResponse,
+    lifecycle::{ConnectionHooks, ConnectionKind, DisconnectReason, HandshakeInfo, Reject},
+    lirpc_message::{
+        LiRpcPayload, LiRpcRequest, LiRpcResponse, LiRpcResponseHeaders, LiRpcResponseResultHeader,
+    },
+    middleware::{Middleware, MiddlewareService},
+    server::{
+        heartbeat::{Beat, Heartbeat, HeartbeatOptions, PING_METHOD},
+        hello::{HELLO_METHOD, Hello},
+        in_flight::{Backlog, InFlightPermit, InFlightRequests, ReadRequest, Sequential},
+        listener::{ACCEPT_BACKOFF, AcceptError, Listener},
+        prefixed_stream::PrefixedStream,
+        reflection::Reflection,
+        timeouts::{IdleTimer, read_within, write_within},
+        upgrade_filter::{UpgradeFilter, read_upgrade_request, refuse_upgrade},
+    },
+    service::{HandlerService, Service, TimeoutService},
     type_definition::TypeDefinition,
 };
 
+mod heartbeat;
+mod hello;
+mod in_flight;
+#[cfg(any(test, feature = "in-memory"))]
+mod in_memory;
+mod listener;
+mod prefixed_stream;
+mod reflection;
+mod timeouts;
+mod upgrade_filter;
+
+#[cfg(any(test, feature = "in-memory"))]
+pub use in_memory::InMemoryClient;
+
 pub struct NamedHandler<S, C> {
     name: String,
     handler: Box<dyn Service<S, C>>,
@@ -38,7 +99,7 @@ impl<S, C> NamedHandler<S, C> {
     where
         F: 'static,
         T: 'static,
-        R: Translatable + 'static,
+        R: IntoLiRpcResponse + 'static,
         S: Send + Sync + Clone + 'static,
         C: Clone + Send + Sync + 'static,
     {
@@ -49,10 +110,92 @@ impl<S, C> NamedHandler<S, C> {
     }
 }
 
+/// Warns about handlers named like the reserved methods of the server, e.g. `lirpc_ping`
+fn warn_reserved<S, C>(handler: &NamedHandler<S, C>) {
+    if handler.name.starts_with(RESERVED_PREFIX) {
+        // Using `eprintln` instead of something from tracing,
+        // as tracing might not have been initialized at this point
+        // and we also want to print this if a user is not using tracing.
+        eprintln!(
+            "Handlers prefixed with '{RESERVED_PREFIX}' are reserved. You should name your methods differently."
+        )
+    }
+}
+
+/// Whether clients have to authenticate themselves with a certificate over mutual TLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Connections of clients without a valid certificate are refused
+    Required,
+    /// Clients without a certificate are accepted, clients with an invalid one are refused
+    Optional,
+}
+
+/// Settings of the server, configured through the `ServerBuilder`
+struct ServerOptions {
+    shutdown_timeout: Duration,
+    /// Applies to methods without a timeout of their own
+    request_timeout: Option<Duration>,
+    method_timeouts: HashMap<String, Duration>,
+    tls: Option<TlsAcceptor>,
+    upgrade_filter: Arc<UpgradeFilter>,
+    #[cfg(unix)]
+    unix_socket_permissions: Option<u32>,
+    max_in_flight_requests: Option<usize>,
+    response_queue_depth: usize,
+    max_connections: Option<usize>,
+    sequential: Arc<Sequential>,
+    max_message_size: usize,
+    read_idle_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    heartbeat: Option<HeartbeatOptions>,
+    /// Told to clients in the `lirpc_hello` handshake
+    api_identity: Option<ApiIdentity>,
+    refuse_incompatible_clients: bool,
+    /// Set when the reflection methods are served
+    reflection: Option<Arc<Reflection>>,
+    /// Methods marked as notifications in the api spec
+    notifications: HashSet<String>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            shutdown_timeout: Duration::from_secs(30),
+            request_timeout: None,
+            method_timeouts: HashMap::new(),
+            tls: None,
+            upgrade_filter: Arc::default(),
+            #[cfg(unix)]
+            unix_socket_permissions: None,
+            max_in_flight_requests: None,
+            response_queue_depth: 10,
+            max_connections: None,
+            sequential: Arc::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            read_idle_timeout: None,
+            write_timeout: None,
+            heartbeat: None,
+            api_identity: None,
+            refuse_incompatible_clients: false,
+            reflection: None,
+            notifications: HashSet::new(),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ServerBuilder<S: Clone, C> {
     handlers: HashMap<String, Box<dyn Service<S, C>>>,
     type_definitions: BTreeMap<String, TypeDefinition>,
+    options: ServerOptions,
+    hooks: ConnectionHooks<C>,
+    /// Applied to all handlers
+    middleware: Vec<Arc<dyn Middleware<S, C>>>,
+    /// The name and version of the api, see `with_api_identity`
+    api: Option<(String, String)>,
+    /// Whether the reflection methods are served, see `with_reflection`
+    reflection: bool,
 }
 
 impl<S, C> ServerBuilder<S, C>
@@ -64,6 +207,11 @@ where
         Self {
             handlers: HashMap::new(),
             type_definitions: BTreeMap::new(),
+            options: ServerOptions::default(),
+            hooks: ConnectionHooks::default(),
+            middleware: Vec::new(),
+            api: None,
+            reflection: false,
         }
     }
 
@@ -81,24 +229,21 @@ where
     where
         F: 'static,
         T: 'static,
-        R: Translatable + 'static,
+        R: IntoLiRpcResponse + 'static,
     {
-        if name.starts_with("lirpc_") {
-            // Using `eprintln` instead of something from tracing,
-            // as tracing might not have been initialized at this point
-            // and we also want to print this if a user is not using tracing.
-            eprintln!(
-                "Handlers prefixed with 'lirpc_' are reserved. You should name your methods differently."
-            )
-        }
-        self.handlers
-            .insert(name, Box::new(HandlerService(Box::new(handler))));
+        let handler = NamedHandler::new(name, handler);
+        warn_reserved(&handler);
+        self.handlers.insert(handler.name, handler.handler);
+
         self
     }
 
     /// Overwrites the set of handlers with the supplied the collection.
     /// Recommended usage is in combination with the `handlers!` macro.
     ///
+    /// The handlers registered before are forgotten, including whether they were
+    /// registered with `with_sequential_handlers` or `with_notification_handlers`.
+    ///
     /// # Example
     /// ```rs
     /// ServerBuilder::new()
@@ -107,16 +252,102 @@ where
     /// ```
     pub fn with_handlers(mut self, handlers: Vec<NamedHandler<S, C>>) -> Self {
         for handler in handlers.iter() {
-            if handler.name.starts_with("lirpc_") {
-                // Using `eprintln` instead of something from tracing,
-                // as tracing might not have been initialized at this point
-                // and we also want to print this if a user is not using tracing.
-                eprintln!(
-                    "Handlers prefixed with 'lirpc_' are reserved. You should name your methods differently."
-                )
-            }
+            warn_reserved(handler);
         }
         self.handlers = handlers.into_iter().map(|h| (h.name, h.handler)).collect();
+        self.sequential().methods.clear();
+        self.options.notifications.clear();
+
+        self
+    }
+
+    /// Adds a group of handlers that share a middleware, which only runs for these handlers.
+    /// Unlike `with_handlers`, this keeps the handlers that were registered before.
+    ///
+    /// # Example
+    /// ```rs
+    /// ServerBuilder::new()
+    ///     .with_handlers(handlers!(login))
+    ///     .with_handler_group(handlers!(profile, logout), require_session)
+    ///     .build()
+    /// ```
+    pub fn with_handler_group(
+        mut self,
+        handlers: Vec<NamedHandler<S, C>>,
+        middleware: impl Middleware<S, C>,
+    ) -> Self {
+        let middleware: Arc<dyn Middleware<S, C>> = Arc::new(middleware);
+
+        for handler in handlers {
+            warn_reserved(&handler);
+            self.handlers.insert(
+                handler.name,
+                MiddlewareService::wrap(middleware.clone(), handler.handler),
+            );
+        }
+
+        self
+    }
+
+    /// Adds handlers whose requests are handled one after the other, in the order they were
+    /// received on the connection. Requests for these handlers wait until the previous one of
+    /// them on the same connection is done, requests for other handlers aren't affected.
+    /// Unlike `with_handlers`, this keeps the handlers that were registered before.
+    ///
+    /// Useful for stateful flows, where e.g. a `login` has to be completed before
+    /// the next request is handled.
+    ///
+    /// # Example
+    /// ```rs
+    /// ServerBuilder::new()
+    ///     .with_handlers(handlers!(greet))
+    ///     .with_sequential_handlers(handlers!(login, protected_function))
+    ///     .build()
+    /// ```
+    pub fn with_sequential_handlers(mut self, handlers: Vec<NamedHandler<S, C>>) -> Self {
+        for handler in handlers {
+            warn_reserved(&handler);
+            self.sequential().methods.insert(handler.name.clone());
+            self.handlers.insert(handler.name, handler.handler);
+        }
+
+        self
+    }
+
+    /// Registers handlers meant to be sent as notifications, which the client sends without
+    /// waiting for a response. They are marked as such in the api spec, so codegen generates
+    /// `notify_*` functions for them. Like any other handler they can still be called normally.
+    /// Unlike `with_handlers`, this keeps the handlers that were registered before.
+    pub fn with_notification_handlers(mut self, handlers: Vec<NamedHandler<S, C>>) -> Self {
+        for handler in handlers {
+            warn_reserved(&handler);
+            self.options.notifications.insert(handler.name.clone());
+            self.handlers.insert(handler.name, handler.handler);
+        }
+
+        self
+    }
+
+    /// Handles all requests of a connection one after the other, in the order they
+    /// were received, instead of concurrently. Connections are still served concurrently.
+    pub fn with_sequential_execution(mut self) -> Self {
+        self.sequential().all = true;
+
+        self
+    }
+
+    fn sequential(&mut self) -> &mut Sequential {
+        Arc::get_mut(&mut self.options.sequential)
+            .expect("the execution order is only shared once the server is built")
+    }
+
+    /// Adds a middleware that runs for every handler of the server, also the ones
+    /// registered after calling this. Middleware added later wraps around middleware
+    /// added earlier, and around the middleware of handler groups.
+    ///
+    /// See `middleware::Middleware` for an example.
+    pub fn layer(mut self, middleware: impl Middleware<S, C>) -> Self {
+        self.middleware.push(Arc::new(middleware));
 
         self
     }
@@ -137,56 +368,450 @@ where
         self
     }
 
+    /// The maximum time a graceful shutdown (see `Server::serve_with_shutdown`)
+    /// waits for in-flight requests to finish. Defaults to 30 seconds.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.options.shutdown_timeout = timeout;
+
+        self
+    }
+
+    /// The maximum time a method gets to respond, after which the handler is cancelled and
+    /// the client receives a `timeout` protocol error. Clients can ask for a shorter timeout
+    /// with the `timeout_ms` request header. By default methods can run indefinitely.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.options.request_timeout = Some(timeout);
+
+        self
+    }
+
+    /// The maximum number of requests of a single connection that are handled at the same time.
+    /// Once reached, the server stops reading from the connection until one of the requests
+    /// is handled and as many requests wait for room, pushing back on the client. Every request
+    /// of a batch counts on its own, cancellations don't wait for room but are handled as soon
+    /// as they are read. By default there is no limit.
+    ///
+    /// # Panics
+    /// When `max` is 0, as no request could ever be handled.
+    pub fn with_max_in_flight_requests(mut self, max: usize) -> Self {
+        assert!(
+            max > 0,
+            "at least one request has to be able to be in flight"
+        );
+        self.options.max_in_flight_requests = Some(max);
+
+        self
+    }
+
+    /// The number of responses that can be queued up per connection, waiting to be written
+    /// to the client. Once full, handlers wait with sending their response (or streamed items)
+    /// until there is room again. Defaults to 10.
+    ///
+    /// # Panics
+    /// When `depth` is 0.
+    pub fn with_response_queue_depth(mut self, depth: usize) -> Self {
+        assert!(
+            depth > 0,
+            "the response queue needs room for at least one response"
+        );
+        self.options.response_queue_depth = depth;
+
+        self
+    }
+
+    /// The maximum number of connections served at the same time. Once reached, the server
+    /// stops accepting new connections until one of the connections is closed; connecting
+    /// clients wait in the backlog of the listener in the meantime. By default there is no limit.
+    ///
+    /// Only applies to connections accepted by `serve` and its variants, not to the
+    /// connections given to `serve_connection`.
+    ///
+    /// # Panics
+    /// When `max` is 0, as no connection could ever be served.
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        assert!(
+            max > 0,
+            "at least one connection has to be able to be served"
+        );
+        self.options.max_connections = Some(max);
+
+        self
+    }
+
+    /// The maximum size (in bytes) of a single message a client can send, over both TCP
+    /// and WebSocket. Connections sending larger messages are closed. Defaults to 8 MiB.
+    pub fn with_max_message_size(mut self, bytes: usize) -> Self {
+        self.options.max_message_size = bytes;
+
+        self
+    }
+
+    /// Closes connections the client hasn't sent anything on for `timeout`, as long as
+    /// none of its requests are still being handled. This also bounds each step of setting up
+    /// a connection, like the TLS handshake or the WebSocket upgrade. By default connections
+    /// can stay idle indefinitely.
+    pub fn with_read_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_idle_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Closes connections when writing a response to them takes longer than `timeout`,
+    /// e.g. because the client stopped reading. By default writes can take indefinitely.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.options.write_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Detects clients that are gone without closing their connection. Once nothing was
+    /// received from a WebSocket client for `interval`, it is sent a ping, and the connection
+    /// is closed when nothing (e.g. the pong) comes back within `timeout`.
+    ///
+    /// Length-delimited connections have no pings of their own, their clients are expected
+    /// to call the reserved `lirpc_ping` method (or send any other message) at least every
+    /// `interval`. Those connections are closed once nothing came in for `interval + timeout`.
+    ///
+    /// While the connection isn't read, as it is at `with_max_in_flight_requests`, the
+    /// heartbeat is paused.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.options.heartbeat = Some(HeartbeatOptions { interval, timeout });
+
+        self
+    }
+
+    /// The name and version of the api the server serves, e.g. `env!("CARGO_PKG_NAME")` and
+    /// `env!("CARGO_PKG_VERSION")`. Together with the fingerprint of the compiled api spec,
+    /// they are told to clients in the `lirpc_hello` handshake, so clients generated from
+    /// another spec are noticed. Incompatible clients are only warned about, unless
+    /// `refuse_incompatible_clients` is set.
+    pub fn with_api_identity(
+        mut self,
+        name: impl Into<String>,
+        version: impl Into<String>,
+    ) -> Self {
+        self.api = Some((name.into(), version.into()));
+
+        self
+    }
+
+    /// Closes the connection of clients that turn out to be incompatible in the `lirpc_hello`
+    /// handshake, after responding with an `incompatible` protocol error
+    pub fn refuse_incompatible_clients(mut self) -> Self {
+        self.options.refuse_incompatible_clients = true;
+
+        self
+    }
+
+    /// Serves the reserved `lirpc_spec` method, responding with the api spec as compiled by
+    /// `Server::compile_api_spec`, and `lirpc_methods`, responding with the names of the
+    /// methods. Lets tooling and dynamic clients discover the api of a running server.
+    /// The spec is named after `with_api_identity`, and left unnamed without.
+    pub fn with_reflection(mut self) -> Self {
+        self.reflection = true;
+
+        self
+    }
+
+    /// Like `with_request_timeout`, for a single method. Takes precedence over
+    /// the timeout set with `with_request_timeout`.
+    pub fn with_method_timeout(mut self, method: impl Into<String>, timeout: Duration) -> Self {
+        self.options.method_timeouts.insert(method.into(), timeout);
+
+        self
+    }
+
+    /// Runs when a client connects, before any of its requests are handled.
+    /// It gets the address of the client (if known), its freshly initialized connection state
+    /// and what is known from the handshake. Returning a `Reject` refuses the connection.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use lirpc::{ServerBuilder, lifecycle::Reject};
+    /// let server = ServerBuilder::new()
+    ///     .on_connect(|peer_addr, authenticated: &mut bool, handshake| {
+    ///         Box::pin(async move {
+    ///             if handshake.peer_identity.is_none() {
+    ///                 return Err(Reject::new(format!("{peer_addr:?} is not authenticated")));
+    ///             }
+    ///
+    ///             *authenticated = true;
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .build_with_connection_state(|| false);
+    /// ```
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: for<'a> Fn(
+                Option<SocketAddr>,
+                &'a mut C,
+                &'a HandshakeInfo,
+            ) -> BoxFuture<'a, Result<(), Reject>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.hooks.on_connect = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Runs when a connection that was accepted by `on_connect` closes.
+    /// Requests of the connection that are still being handled keep running.
+    pub fn on_disconnect<F>(mut self, hook: F) -> Self
+    where
+        F: for<'a> Fn(&'a C, DisconnectReason) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+    {
+        self.hooks.on_disconnect = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Only accepts WebSocket upgrades from browsers on one of the given origins,
+    /// e.g. `https://example.com`. Other upgrades are refused with `403 Forbidden`.
+    ///
+    /// Upgrade requests without an `Origin` header (i.e. not from a browser) are accepted.
+    pub fn with_allowed_origins<O>(mut self, origins: impl IntoIterator<Item = O>) -> Self
+    where
+        O: Into<String>,
+    {
+        self.upgrade_filter().allowed_origins = Some(origins.into_iter().map(Into::into).collect());
+
+        self
+    }
+
+    /// Only accepts WebSocket upgrades to the given path, e.g. `/rpc`.
+    /// Upgrades to other paths are refused with `404 Not Found`.
+    pub fn with_websocket_path(mut self, path: impl Into<String>) -> Self {
+        self.upgrade_filter().path = Some(path.into());
+
+        self
+    }
+
+    /// Runs for every WebSocket upgrade request that passed the allowed origins
+    /// and the WebSocket path, before the connection is established.
+    /// Returning an HTTP status refuses the upgrade with that status.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use lirpc::{ServerBuilder, http::StatusCode};
+    /// let server = ServerBuilder::new()
+    ///     .on_upgrade(|upgrade| {
+    ///         Box::pin(async move {
+    ///             match upgrade.headers().get("authorization") {
+    ///                 Some(_) => Ok(()),
+    ///                 None => Err(StatusCode::UNAUTHORIZED),
+    ///             }
+    ///         })
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on_upgrade<F>(mut self, hook: F) -> Self
+    where
+        F: for<'a> Fn(&'a UpgradeHeaders) -> BoxFuture<'a, Result<(), StatusCode>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.upgrade_filter().on_upgrade = Some(Box::new(hook));
+
+        self
+    }
+
+    fn upgrade_filter(&mut self) -> &mut UpgradeFilter {
+        Arc::get_mut(&mut self.options.upgrade_filter)
+            .expect("the upgrade filter is only shared once the server is built")
+    }
+
+    /// The permissions (e.g. `0o660`) the socket file gets when serving on a
+    /// Unix domain socket, see `Server::serve_unix`. The socket only shows up at its
+    /// path once it has them. By default the permissions follow from the umask of the process.
+    #[cfg(unix)]
+    pub fn with_unix_socket_permissions(mut self, mode: u32) -> Self {
+        self.options.unix_socket_permissions = Some(mode);
+
+        self
+    }
+
+    /// Terminates TLS on every connection with the given rustls config,
+    /// for both the TCP and the WebSocket (wss) clients.
+    pub fn with_tls_config(mut self, config: impl Into<Arc<ServerConfig>>) -> Self {
+        self.options.tls = Some(TlsAcceptor::from(config.into()));
+
+        self
+    }
+
+    /// Terminates TLS on every connection with the certificate (chain) and private key
+    /// read from the given PEM files. See `with_tls_config` for more control over the
+    /// TLS configuration.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # use lirpc::ServerBuilder;
+    /// let server = ServerBuilder::new()
+    ///     .with_tls_pem_files("cert.pem", "key.pem")
+    ///     .unwrap()
+    ///     .build();
+    /// ```
+    pub fn with_tls_pem_files(
+        self,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, LiRpcError> {
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                CertificateDer::pem_file_iter(cert_path)?.collect::<Result<_, _>>()?,
+                PrivateKeyDer::from_pem_file(key_path)?,
+            )?;
+
+        Ok(self.with_tls_config(config))
+    }
+
+    /// Like `with_tls_pem_files`, but also verifies client certificates against the
+    /// CA certificate(s) in `client_ca_path`. Whether clients have to present a certificate
+    /// is decided by `client_auth`.
+    ///
+    /// The identity of a verified client is available to handlers
+    /// through the `PeerIdentity` extractor.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # use lirpc::{ClientAuth, ServerBuilder};
+    /// let server = ServerBuilder::new()
+    ///     .with_mutual_tls_pem_files("cert.pem", "key.pem", "client-ca.pem", ClientAuth::Required)
+    ///     .unwrap()
+    ///     .build();
+    /// ```
+    pub fn with_mutual_tls_pem_files(
+        self,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+        client_ca_path: impl AsRef<Path>,
+        client_auth: ClientAuth,
+    ) -> Result<Self, LiRpcError> {
+        let mut client_roots = RootCertStore::empty();
+        for certificate in CertificateDer::pem_file_iter(client_ca_path)? {
+            client_roots.add(certificate?)?;
+        }
+
+        let verifier = WebPkiClientVerifier::builder(Arc::new(client_roots));
+        let verifier = match client_auth {
+            ClientAuth::Required => verifier.build()?,
+            ClientAuth::Optional => verifier.allow_unauthenticated().build()?,
+        };
+
+        let config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                CertificateDer::pem_file_iter(cert_path)?.collect::<Result<_, _>>()?,
+                PrivateKeyDer::from_pem_file(key_path)?,
+            )?;
+
+        Ok(self.with_tls_config(config))
+    }
+
     pub fn build_with_state_and_connection_state(
         self,
         state: S,
-        default_connection_state: impl Fn() -> C + 'static,
+        default_connection_state: impl Fn() -> C + Send + Sync + 'static,
+    ) -> Server<S, C> {
+        self.into_server(state, Box::new(default_connection_state))
+    }
+}
+
+impl<S, C> ServerBuilder<S, C>
+where
+    S: Send + Sync + Clone + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    fn into_server(
+        self,
+        state: S,
+        connection_state_initializer: Box<dyn Fn() -> C + Send + Sync>,
     ) -> Server<S, C> {
-        Server {
+        let mut handlers = self.handlers;
+        for middleware in self.middleware {
+            handlers = handlers
+                .into_iter()
+                .map(|(name, handler)| (name, MiddlewareService::wrap(middleware.clone(), handler)))
+                .collect();
+        }
+
+        let handlers = handlers
+            .into_iter()
+            .map(|(name, handler)| {
+                let timeout = self
+                    .options
+                    .method_timeouts
+                    .get(&name)
+                    .copied()
+                    .or(self.options.request_timeout);
+
+                (name, TimeoutService::wrap(handler, timeout))
+            })
+            .collect();
+
+        let mut server = Server {
             state,
-            handlers: Arc::new(self.handlers),
+            handlers: Arc::new(handlers),
             type_definitions: Arc::new(self.type_definitions),
-            connection_state_initializer: Box::new(default_connection_state),
+            connection_state_initializer,
+            options: self.options,
+            hooks: Arc::new(self.hooks),
+        };
+
+        if self.api.is_some() || self.reflection {
+            let (name, version) = self.api.clone().unwrap_or_default();
+            match server.compile_api_spec(name, version) {
+                Ok(spec) => {
+                    if self.api.is_some() {
+                        server.options.api_identity = Some(spec.identity());
+                    }
+                    if self.reflection {
+                        match Reflection::new(&spec) {
+                            Ok(reflection) => {
+                                server.options.reflection = Some(Arc::new(reflection))
+                            }
+                            Err(e) => {
+                                error!("Unable to serialize the api spec for reflection: {e}")
+                            }
+                        }
+                    }
+                }
+                Err(e) => error!("Unable to compile the api spec, it is not served: {e}"),
+            }
         }
+
+        server
     }
 }
 
 impl<S> ServerBuilder<S, ()>
 where
-    S: Clone,
+    S: Send + Sync + Clone + 'static,
 {
     pub fn build_with_state(self, state: S) -> Server<S, ()> {
-        Server {
-            state,
-            handlers: Arc::new(self.handlers),
-            type_definitions: Arc::new(self.type_definitions),
-            connection_state_initializer: Box::new(|| ()),
-        }
+        self.into_server(state, Box::new(|| ()))
     }
 }
 
 impl ServerBuilder<(), ()> {
     pub fn build(self) -> Server<(), ()> {
-        Server {
-            state: (),
-            handlers: Arc::new(self.handlers),
-            type_definitions: Arc::new(self.type_definitions),
-            connection_state_initializer: Box::new(|| ()),
-        }
+        self.into_server((), Box::new(|| ()))
     }
 }
 
-impl<C> ServerBuilder<(), C> {
+impl<C> ServerBuilder<(), C>
+where
+    C: Clone + Send + Sync + 'static,
+{
     pub fn build_with_connection_state(
         self,
-        default_connection_state: impl Fn() -> C + 'static,
+        default_connection_state: impl Fn() -> C + Send + Sync + 'static,
     ) -> Server<(), C> {
-        Server {
-            state: (),
-            handlers: Arc::new(self.handlers),
-            type_definitions: Arc::new(self.type_definitions),
-            connection_state_initializer: Box::new(default_connection_state),
-        }
+        self.into_server((), Box::new(default_connection_state))
     }
 }
 
@@ -194,7 +819,47 @@ pub struct Server<S: Clone, C> {
     state: S,
     handlers: Arc<HashMap<String, Box<dyn Service<S, C>>>>,
     type_definitions: Arc<BTreeMap<String, TypeDefinition>>,
-    connection_state_initializer: Box<dyn Fn() -> C>,
+    connection_state_initializer: Box<dyn Fn() -> C + Send + Sync>,
+    options: ServerOptions,
+    hooks: Arc<ConnectionHooks<C>>,
+}
+
+/// What is known about the client on the other end of a connection
+#[derive(Default)]
+struct PeerInfo {
+    /// Not known for e.g. Unix domain sockets
+    addr: Option<SocketAddr>,
+    /// Set when the client presented a (verified) certificate over TLS
+    identity: Option<PeerIdentity>,
+}
+
+/// What a connection task needs from the server
+struct ConnectionContext<S, C> {
+    state: S,
+    handlers: Arc<HashMap<String, Box<dyn Service<S, C>>>>,
+    /// Cancelled when the server starts shutting down
+    shutdown: CancellationToken,
+    /// Tracks every task spawned by the server, to wait on during shutdown
+    tasks: TaskTracker,
+    /// Set when TLS has to be terminated on the connection
+    tls: Option<TlsAcceptor>,
+    upgrade_filter: Arc<UpgradeFilter>,
+    hooks: Arc<ConnectionHooks<C>>,
+    max_in_flight_requests: Option<usize>,
+    response_queue_depth: usize,
+    sequential: Arc<Sequential>,
+    max_message_size: usize,
+    read_idle_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    heartbeat: Option<HeartbeatOptions>,
+    /// Negotiated once the connection is established
+    codec: Codec,
+    /// What the server says in the `lirpc_hello` handshake
+    hello: Arc<Hello>,
+    refuse_incompatible_clients: bool,
+    reflection: Option<Arc<Reflection>>,
+    /// Cancelled to close the connection, once the responses queued before are sent
+    closing: CancellationToken,
 }
 
 impl<S, C> Server<S, C>
@@ -211,16 +876,51 @@ where
     ) -> Result<(), LiRpcError> {
         debug!("Received message: {message:?}");
 
-        let handler =
-            handlers
-                .get(&message.headers.function)
-                .ok_or(LiRpcError::HandlerNotFound(
-                    message.headers.function.to_string(),
-                ))?;
-
         let message_id = message.headers.id;
+        let notification = message.headers.notification;
+
+        let response = match handlers.get(&message.headers.function) {
+            Some(handler) => {
+                let method = message.headers.function.clone();
+
+                // A panicking handler must not leave the client waiting for a response forever,
+                // whether it panics creating its future (e.g. in a middleware) or polling it
+                let handled = match panic::catch_unwind(AssertUnwindSafe(|| {
+                    handler.call(connection, message, state)
+                })) {
+                    Ok(handling) => AssertUnwindSafe(handling).catch_unwind().await,
+                    Err(panic) => Err(panic),
+                };
+
+                match handled {
+                    Ok(response) => response,
+                    Err(panic) => {
+                        error!(
+                            "Handler of method {method} panicked handling message ({message_id}): {}",
+                            panic_message(&panic)
+                        );
+
+                        LiRpcResponse::from_protocol_error(
+                            message_id,
+                            LiRpcProtocolError::server_error(),
+                        )
+                    }
+                }
+            }
+            None => {
+                debug!("Method {} not found", message.headers.function);
+
+                LiRpcResponse::from_protocol_error(
+                    message_id,
+                    LiRpcProtocolError::UnknownMethod(message.headers.function),
+                )
+            }
+        };
 
-        let response = handler.call(connection, message, state).await;
+        if notification {
+            debug!("Not responding to notification ({message_id})");
+            return Ok(());
+        }
 
         if let Err(e) = output.send(response).await {
             error!("Error sending response for message ({message_id}): {e}");
@@ -229,167 +929,793 @@ where
         Ok(())
     }
 
-    async fn handle_tcp_connection(
-        stream: TcpStream,
-        state: S,
-        new_connection_details: ConnectionDetails<C>,
-        handlers: Arc<HashMap<String, Box<dyn Service<S, C>>>>,
+    /// Spawns the handling of a single request on the given connection
+    fn spawn_request_handler(
+        context: &ConnectionContext<S, C>,
+        connection_tasks: &TaskTracker,
+        connection_details: &Arc<ConnectionDetails<C>>,
+        output: &mpsc::Sender<LiRpcResponse>,
+        in_flight: &InFlightRequests,
+        permit: InFlightPermit,
+        request: ReadRequest,
     ) {
-        let framed = LengthDelimitedCodec::builder()
-            .max_frame_length(MAX_TCP_FRAME_LENGTH)
-            .new_framed(stream);
+        let message = match request {
+            Ok(m) => m,
+            Err((Some(message_id), e)) => {
+                debug!("Error deserializing message ({message_id}): {e}");
 
-        let (mut frame_sender, mut frame_receiver) = framed.split();
-        let (tx, mut rx) = mpsc::channel(10);
-
-        let connection_details = Arc::new(new_connection_details);
+                let response = LiRpcResponse::from_protocol_error(message_id, e);
+                Self::respond(context, connection_tasks, output, response, permit);
 
-        loop {
-            tokio::select! {
-                frame = frame_receiver.next() => {
-                    match frame {
-                        Some(Ok(bytes)) => {
-                            let handlers_clone = handlers.clone();
-                            let tx_clone = tx.clone();
-                            let state_clone = state.clone();
-                            let connection_clone = connection_details.clone();
-
-                            tokio::spawn(async move {
-                                let message = match serde_json::from_slice::<LiRpcRequest>(&bytes) {
-                                    Ok(m) => m,
-                                    Err(e) => {
-                                        error!("Error deserializing message: {e}");
-                                        return;
-                                    }
-                                };
+                return;
+            }
+            Err((None, e)) => {
+                error!("Error deserializing message: {e}");
+                return;
+            }
+        };
 
-                                if let Err(e) = Self::handle_message(handlers_clone, message, state_clone, connection_clone, tx_clone).await {
-                                    match e {
-                                        LiRpcError::OutputStreamClosed => {},
-                                        _ => error!("Error during handling of message: {e}"),
-                                    };
-                                }
-                            });
-                        }
-                        Some(Err(e)) => {
-                            debug!("Error receiving TCP frame: {e}");
-                            break;
-                        }
-                        None => break,
-                    }
-                }
+        let message_id = message.headers.id;
+
+        // Reserved methods only answer, which notifications never get
+        if message.headers.notification && message.headers.function.starts_with(RESERVED_PREFIX) {
+            debug!(
+                "Ignoring notification for {} ({message_id})",
+                message.headers.function
+            );
+            return;
+        }
+
+        if message.headers.function == HELLO_METHOD {
+            let response = Self::answer_hello(context, message);
+            // Closing the connection once the refusal is queued, so it is still sent
+            let closing = response
+                .headers
+                .protocol_error
+                .then(|| context.closing.clone().drop_guard());
+            Self::respond(
+                context,
+                connection_tasks,
+                output,
+                response,
+                (permit, closing),
+            );
+
+            return;
+        }
+
+        let reflected = context
+            .reflection
+            .as_ref()
+            .and_then(|reflection| reflection.answer(&message.headers.function));
+
+        if message.headers.function == PING_METHOD || reflected.is_some() {
+            let response = match reflected {
+                Some(answer) => LiRpcResponse::new(
+                    LiRpcResponseHeaders::new(message_id, LiRpcResponseResultHeader::Ok),
+                    Some(LiRpcPayload::new(answer.clone())),
+                ),
+                None => ().into_lirpc_response(message_id),
+            };
+            Self::respond(context, connection_tasks, output, response, permit);
+
+            return;
+        }
+
+        let handlers_clone = context.handlers.clone();
+        let tx_clone = output.clone();
+        let state_clone = context.state.clone();
+        let connection_clone = connection_details.clone();
+        let in_flight_clone = in_flight.clone();
+        let mut turn = context
+            .sequential
+            .applies_to(&message.headers.function)
+            .then(|| in_flight.take_turn());
+
+        let notification = message.headers.notification;
+        let handling = connection_tasks.track_future(async move {
+            if let Some(turn) = &mut turn {
+                turn.wait().await;
+            }
+
+            if let Err(e) = Self::handle_message(
+                handlers_clone,
+                message,
+                state_clone,
+                connection_clone,
+                tx_clone,
+            )
+            .await
+            {
+                match e {
+                    LiRpcError::OutputStreamClosed => {}
+                    _ => error!("Error during handling of message: {e}"),
+                };
+            }
+
+            // Another request may be in flight under the id of a notification
+            if !notification {
+                in_flight_clone.finish(message_id);
+            }
+            drop(turn);
+            drop(permit);
+        });
+
+        // Notifications can't be cancelled, their ids don't have to be unique
+        if notification {
+            context.tasks.spawn(handling);
+        } else {
+            in_flight.track(message_id, || context.tasks.spawn(handling).abort_handle());
+        }
+    }
+
+    /// Sends a response that is ready right away from a task of its own, as the response
+    /// queue can be full. `permit` is held on to until the response is queued.
+    fn respond(
+        context: &ConnectionContext<S, C>,
+        connection_tasks: &TaskTracker,
+        output: &mpsc::Sender<LiRpcResponse>,
+        response: LiRpcResponse,
+        permit: impl Send + 'static,
+    ) {
+        let message_id = response.headers.id;
+        let output = output.clone();
+
+        context
+            .tasks
+            .spawn(connection_tasks.track_future(async move {
+                if let Err(e) = output.send(response).await {
+                    error!("Error sending response for message ({message_id}): {e}");
+                };
+                drop(permit);
+            }));
+    }
+
+    /// Answers the `lirpc_hello` handshake of a client with the hello of the server,
+    /// or refuses it with an `incompatible` protocol error
+    fn answer_hello(context: &ConnectionContext<S, C>, message: LiRpcRequest) -> LiRpcResponse {
+        let message_id = message.headers.id;
+
+        let hello: Hello = match message
+            .payload
+            .map(|payload| serde_json::from_value(payload.0))
+            .transpose()
+        {
+            Ok(Some(hello)) => hello,
+            Ok(None) => Hello::new(None),
+            Err(e) => {
+                return LiRpcResponse::from_protocol_error(
+                    message_id,
+                    LiRpcProtocolError::MalformedPayload(e.to_string()),
+                );
+            }
+        };
+
+        if let Some(incompatibility) = hello.incompatibility(&context.hello) {
+            if context.refuse_incompatible_clients {
+                info!("Refusing incompatible client: {incompatibility}");
+                return LiRpcResponse::from_protocol_error(
+                    message_id,
+                    LiRpcProtocolError::Incompatible(incompatibility),
+                );
+            }
+
+            warn!("Incompatible client connected: {incompatibility}");
+        }
+
+        match serde_json::to_value(context.hello.as_ref()) {
+            Ok(answer) => LiRpcResponse::new(
+                LiRpcResponseHeaders::new(message_id, LiRpcResponseResultHeader::Ok),
+                Some(LiRpcPayload::new(answer)),
+            ),
+            Err(e) => {
+                error!("Error serializing hello: {e}");
+                LiRpcResponse::from_protocol_error(message_id, LiRpcProtocolError::server_error())
+            }
+        }
+    }
+
+    async fn handle_tcp_connection<I>(
+        stream: I,
+        mut context: ConnectionContext<S, C>,
+        mut connection_state: C,
+        peer: PeerInfo,
+    ) where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let handshake = HandshakeInfo {
+            kind: ConnectionKind::Tcp,
+            peer_identity: peer.identity,
+            upgrade_headers: None,
+        };
+        if let Err(reject) = context
+            .hooks
+            .connect(peer.addr, &mut connection_state, &handshake)
+            .await
+        {
+            debug!("Rejected TCP connection: {}", reject.reason);
+            return;
+        }
+
+        let framed = LengthDelimitedCodec::builder()
+            .max_frame_length(context.max_message_size)
+            .new_framed(stream);
+
+        let (mut frame_sender, mut frame_receiver) = framed.split();
+        let (tx, mut rx) = mpsc::channel(context.response_queue_depth);
+
+        let connection_details = Arc::new(
+            ConnectionDetails::new(connection_state, tx.clone())
+                .with_peer_addr(peer.addr)
+                .with_peer_identity(handshake.peer_identity)
+                .with_upgrade_headers(handshake.upgrade_headers),
+        );
+        // The handlers running for this connection, to drain during shutdown
+        let connection_tasks = TaskTracker::new();
+        let in_flight = InFlightRequests::new(context.max_in_flight_requests);
+        // The requests read but still waiting for room to be handled, each takes a permit
+        let mut pending = Backlog::new(context.max_in_flight_requests);
+        let mut idle = IdleTimer::new(context.read_idle_timeout);
+        let mut heartbeat = Heartbeat::new(context.heartbeat);
+        let mut draining = false;
+        // The first frame can be a handshake picking the codec
+        let mut first_frame = true;
+
+        let reason = loop {
+            tokio::select! {
+                // Send out the responses that are ready before stopping the connection
+                biased;
 
                 Some(response) = rx.recv() => {
-                    let serialized_response = match serde_json::to_vec(&response) {
+                    let serialized_response = match context.codec.encode(&response) {
                         Ok(bytes) => Bytes::from(bytes),
                         Err(e) => {
                             error!("Error serializing response: {e}");
-                            break;
+                            break DisconnectReason::Error(e.to_string());
                         }
                     };
 
-                    if let Err(e) = frame_sender.send(serialized_response).await {
+                    if let Err(e) = write_within(context.write_timeout, frame_sender.send(serialized_response)).await {
                         error!("Error sending TCP response: {e}");
-                        break;
+                        break DisconnectReason::Error(e);
+                    }
+                }
+
+                _ = connection_tasks.wait(), if draining => {
+                    if let Err(e) = frame_sender.close().await {
+                        debug!("Error closing TCP connection: {e}");
+                    }
+                    break DisconnectReason::Shutdown;
+                }
+
+                _ = context.shutdown.cancelled(), if !draining => {
+                    draining = true;
+                    connection_tasks.close();
+                }
+
+                _ = context.closing.cancelled(), if !draining => {
+                    if let Err(e) = frame_sender.close().await {
+                        debug!("Error closing TCP connection: {e}");
+                    }
+                    break DisconnectReason::Incompatible;
+                }
+
+                permit = in_flight.reserve(), if !pending.is_empty() && !draining => {
+                    let request = pending.pop().expect("only reserving for pending requests");
+                    Self::spawn_request_handler(&context, &connection_tasks, &connection_details, &tx, &in_flight, permit, request);
+                }
+
+                _ = idle.elapsed(), if !draining => {
+                    // Notifications aren't tracked as in flight, but keep the connection busy as well
+                    if !connection_tasks.is_empty() || !pending.is_empty() {
+                        idle.reset();
+                        continue;
+                    }
+
+                    info!("Closing idle TCP connection, nothing was received for {:?}", idle.timeout().unwrap_or_default());
+                    if let Err(e) = frame_sender.close().await {
+                        debug!("Error closing TCP connection: {e}");
+                    }
+                    break DisconnectReason::Idle;
+                }
+
+                _ = heartbeat.due(), if !draining => {
+                    // Nothing is read while the backlog is full, the client isn't to blame
+                    if !pending.has_room() {
+                        heartbeat.received();
+                        continue;
+                    }
+
+                    // The client is expected to ping over length-delimited connections
+                    let Beat::Missed = heartbeat.beat() else {
+                        continue;
+                    };
+
+                    info!("Closing unresponsive TCP connection, no heartbeat was received");
+                    if let Err(e) = frame_sender.close().await {
+                        debug!("Error closing TCP connection: {e}");
+                    }
+                    break DisconnectReason::Unresponsive;
+                }
+
+                frame = frame_receiver.next(), if pending.has_room() && !draining => {
+                    idle.reset();
+                    heartbeat.received();
+
+                    match frame {
+                        Some(Ok(bytes)) => {
+                            if std::mem::take(&mut first_frame)
+                                && let Some(handshake) = CodecHandshake::from_first_frame(&bytes)
+                            {
+                                context.codec = Codec::from_name(&handshake.codec).unwrap_or_default();
+                                debug!("Client asked for codec {}, using {}", handshake.codec, context.codec.name());
+
+                                let answer = Bytes::from(CodecHandshake::answer(context.codec));
+                                if let Err(e) = write_within(context.write_timeout, frame_sender.send(answer)).await {
+                                    error!("Error answering codec handshake: {e}");
+                                    break DisconnectReason::Error(e);
+                                }
+                                continue;
+                            }
+
+                            for request in LiRpcRequest::decode_frame(context.codec, &bytes) {
+                                pending.push(request, &in_flight);
+                            }
+                        }
+                        Some(Err(e)) => {
+                            debug!("Error receiving TCP frame: {e}");
+                            break DisconnectReason::Error(e.to_string());
+                        }
+                        None => break DisconnectReason::Closed,
                     }
                 }
             }
-        }
+        };
+
+        context
+            .hooks
+            .disconnect(&connection_details.connection_state, reason)
+            .await;
     }
 
-    async fn handle_ws_connection(
-        stream: TcpStream,
-        state: S,
-        new_connection_details: ConnectionDetails<C>,
-        handlers: Arc<HashMap<String, Box<dyn Service<S, C>>>>,
-    ) {
-        let socket = match accept_async(stream).await {
-            Ok(s) => s,
-            Err(e) => {
+    async fn handle_ws_connection<I>(
+        stream: I,
+        mut context: ConnectionContext<S, C>,
+        mut connection_state: C,
+        peer: PeerInfo,
+    ) where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut upgrade_headers = None;
+        let mut codec = Codec::default();
+        // The size of the error is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let accepting = accept_hdr_async_with_config(
+            stream,
+            |request: &Request, mut response: Response| {
+                upgrade_headers = Some(UpgradeHeaders::new(
+                    request.uri().clone(),
+                    request.headers().clone(),
+                ));
+
+                // Without a supported subprotocol the client falls back to JSON, or gives up
+                if let Some(negotiated) = request
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|protocols| protocols.to_str().ok())
+                    .and_then(Codec::from_subprotocols)
+                    && let Ok(protocol) = HeaderValue::from_str(&negotiated.subprotocol())
+                {
+                    codec = negotiated;
+                    response
+                        .headers_mut()
+                        .insert(SEC_WEBSOCKET_PROTOCOL, protocol);
+                }
+
+                Ok(response)
+            },
+            Some(
+                WebSocketConfig::default()
+                    .max_message_size(Some(context.max_message_size))
+                    .max_frame_size(Some(context.max_message_size)),
+            ),
+        );
+        let accepted = read_within(context.read_idle_timeout, accepting).await;
+
+        let mut socket = match accepted {
+            Some(Ok(s)) => s,
+            Some(Err(e)) => {
                 warn!("establishing ws connection with client failed: {e}");
                 return;
             }
+            None => {
+                info!("Closing idle connection, the websocket upgrade wasn't completed in time");
+                return;
+            }
+        };
+        context.codec = codec;
+
+        let handshake = HandshakeInfo {
+            kind: ConnectionKind::WebSocket,
+            peer_identity: peer.identity,
+            upgrade_headers,
         };
+        if let Err(reject) = context
+            .hooks
+            .connect(peer.addr, &mut connection_state, &handshake)
+            .await
+        {
+            debug!("Rejected websocket connection: {}", reject.reason);
+
+            let close_frame = CloseFrame {
+                code: CloseCode::Policy,
+                reason: reject.reason.into(),
+            };
+            if let Err(e) = socket.close(Some(close_frame)).await {
+                debug!("Error closing websocket connection: {e}");
+            }
+            return;
+        }
 
         let (mut ws_sender, mut ws_receiver) = socket.split();
-        let (tx, mut rx) = mpsc::channel(10);
+        let (tx, mut rx) = mpsc::channel(context.response_queue_depth);
 
-        let connection_details = Arc::new(new_connection_details);
+        let connection_details = Arc::new(
+            ConnectionDetails::new(connection_state, tx.clone())
+                .with_peer_addr(peer.addr)
+                .with_peer_identity(handshake.peer_identity)
+                .with_upgrade_headers(handshake.upgrade_headers),
+        );
+        // The handlers running for this connection, to drain during shutdown
+        let connection_tasks = TaskTracker::new();
+        let in_flight = InFlightRequests::new(context.max_in_flight_requests);
+        // The requests read but still waiting for room to be handled, each takes a permit
+        let mut pending = Backlog::new(context.max_in_flight_requests);
+        let mut idle = IdleTimer::new(context.read_idle_timeout);
+        let mut heartbeat = Heartbeat::new(context.heartbeat);
+        let mut draining = false;
 
-        loop {
+        let reason = loop {
             tokio::select! {
-                msg = ws_receiver.next() => {
-                    match msg {
-                        Some(Ok(message)) => {
-                            if message.is_close() || message.is_ping() || message.is_pong() {
-                                break;
-                            }
-                            let handlers_clone = handlers.clone();
-                            let tx_clone = tx.clone();
-                            let state_clone = state.clone();
-                            let connection_clone = connection_details.clone();
-
-                            tokio::spawn(async move {
-                                let message = match LiRpcRequest::try_from(message) {
-                                    Ok(m) => m,
-                                    Err(e) => {
-                                        error!("Error deserializing message: {e}");
-                                        return;
-                                    }
-                                };
-
-                                if let Err(e) = Self::handle_message(handlers_clone, message, state_clone, connection_clone, tx_clone).await {
-                                    match e {
-                                        LiRpcError::OutputStreamClosed => {},
-                                        _ => error!("Error during handling of message: {e}"),
-                                    };
-                                }
-                            });
-                        }
-                        Some(Err(e)) => {
-                            debug!("Error receiving message: {e}");
-                            break;
-                        }
-                        None => break,
-                    }
-                }
+                // Send out the responses that are ready before stopping the connection
+                biased;
 
                 Some(response) = rx.recv() => {
-                    let serialized_response = match response.try_into() {
+                    let serialized_response = match Self::encode_ws_message(context.codec, &response) {
                         Ok(r) => r,
                         Err(e) => {
                             error!("Error serializing response: {e}");
-                            break;
+                            break DisconnectReason::Error(e.to_string());
                         }
                     };
 
-                    if let Err(e) = ws_sender.send(serialized_response).await {
+                    if let Err(e) = write_within(context.write_timeout, ws_sender.send(serialized_response)).await {
                         error!("Error sending response: {e}");
-                        break;
+                        break DisconnectReason::Error(e);
+                    }
+                }
+
+                _ = connection_tasks.wait(), if draining => {
+                    let close_frame = CloseFrame {
+                        code: CloseCode::Away,
+                        reason: "server is shutting down".into(),
+                    };
+
+                    if let Err(e) = ws_sender.send(Message::Close(Some(close_frame))).await {
+                        debug!("Error closing websocket connection: {e}");
+                    }
+                    break DisconnectReason::Shutdown;
+                }
+
+                _ = context.shutdown.cancelled(), if !draining => {
+                    draining = true;
+                    connection_tasks.close();
+                }
+
+                _ = context.closing.cancelled(), if !draining => {
+                    let close_frame = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: "incompatible client".into(),
+                    };
+                    if let Err(e) = ws_sender.send(Message::Close(Some(close_frame))).await {
+                        debug!("Error closing websocket connection: {e}");
+                    }
+                    break DisconnectReason::Incompatible;
+                }
+
+                permit = in_flight.reserve(), if !pending.is_empty() && !draining => {
+                    let request = pending.pop().expect("only reserving for pending requests");
+                    Self::spawn_request_handler(&context, &connection_tasks, &connection_details, &tx, &in_flight, permit, request);
+                }
+
+                _ = idle.elapsed(), if !draining => {
+                    // Notifications aren't tracked as in flight, but keep the connection busy as well
+                    if !connection_tasks.is_empty() || !pending.is_empty() {
+                        idle.reset();
+                        continue;
+                    }
+
+                    info!("Closing idle websocket connection, nothing was received for {:?}", idle.timeout().unwrap_or_default());
+                    let close_frame = CloseFrame {
+                        code: CloseCode::Away,
+                        reason: "connection was idle".into(),
+                    };
+                    if let Err(e) = ws_sender.send(Message::Close(Some(close_frame))).await {
+                        debug!("Error closing websocket connection: {e}");
+                    }
+                    break DisconnectReason::Idle;
+                }
+
+                _ = heartbeat.due(), if !draining => {
+                    // Nothing is read while the backlog is full, so pongs couldn't be seen
+                    if !pending.has_room() {
+                        heartbeat.received();
+                        continue;
+                    }
+
+                    if let Beat::Ping = heartbeat.beat() {
+                        if let Err(e) = write_within(context.write_timeout, ws_sender.send(Message::Ping(Bytes::new()))).await {
+                            debug!("Error sending ping: {e}");
+                            break DisconnectReason::Error(e);
+                        }
+                        continue;
+                    }
+
+                    info!("Closing unresponsive websocket connection, no pong was received within {:?}", heartbeat.timeout());
+                    let close_frame = CloseFrame {
+                        code: CloseCode::Away,
+                        reason: "no pong was received".into(),
+                    };
+                    if let Err(e) = write_within(context.write_timeout, ws_sender.send(Message::Close(Some(close_frame)))).await {
+                        debug!("Error closing websocket connection: {e}");
+                    }
+                    break DisconnectReason::Unresponsive;
+                }
+
+                msg = ws_receiver.next(), if pending.has_room() && !draining => {
+                    idle.reset();
+                    heartbeat.received();
+
+                    match msg {
+                        Some(Ok(message)) => {
+                            match message {
+                                Message::Close(_) => break DisconnectReason::Closed,
+                                Message::Ping(payload) => {
+                                    if let Err(e) = write_within(context.write_timeout, ws_sender.send(Message::Pong(payload))).await {
+                                        debug!("Error sending pong: {e}");
+                                        break DisconnectReason::Error(e);
+                                    }
+                                    continue;
+                                }
+                                // Any message counts as a sign of life, pongs don't need more handling
+                                Message::Pong(_) => continue,
+                                _ => {}
+                            }
+                            let raw_message = match message {
+                                Message::Text(text) => Bytes::from(text),
+                                Message::Binary(bytes) => bytes,
+                                _ => {
+                                    error!("Error deserializing message: {}", LiRpcError::UnableToParseWebsocketMessage);
+                                    continue;
+                                }
+                            };
+
+                            for request in LiRpcRequest::decode_frame(context.codec, &raw_message) {
+                                pending.push(request, &in_flight);
+                            }
+                        }
+                        Some(Err(e)) => {
+                            debug!("Error receiving message: {e}");
+                            break DisconnectReason::Error(e.to_string());
+                        }
+                        None => break DisconnectReason::Closed,
                     }
                 }
             }
+        };
+
+        context
+            .hooks
+            .disconnect(&connection_details.connection_state, reason)
+            .await;
+    }
+
+    /// Encodes a response as text or binary message, depending on the codec
+    fn encode_ws_message(codec: Codec, response: &LiRpcResponse) -> Result<Message, CodecError> {
+        let encoded = codec.encode(response)?;
+
+        if codec.is_binary() {
+            return Ok(Message::Binary(encoded.into()));
         }
+
+        String::from_utf8(encoded)
+            .map(|text| Message::Text(text.into()))
+            .map_err(|e| CodecError::Encode(e.to_string()))
     }
 
-    async fn classify_connection(stream: &TcpStream) -> ConnectionKind {
+    /// Reads the first bytes of a connection to classify it. Those bytes
+    /// are returned as well, as they still have to be handled.
+    async fn classify_connection<I>(stream: &mut I) -> (ConnectionKind, Bytes)
+    where
+        I: AsyncRead + Unpin,
+    {
         let mut buf = [0u8; 1024];
-        let n = match stream.peek(&mut buf).await {
+        let n = match stream.read(&mut buf).await {
             Ok(n) => n,
             Err(e) => {
                 warn!(
                     "Failed to classify connection type. Falling back to TCP. Original error: {e}"
                 );
-                return ConnectionKind::Tcp;
+                return (ConnectionKind::Tcp, Bytes::new());
             }
         };
 
         let data = &buf[..n];
 
-        if Self::is_websocket_upgrade(data) {
+        let kind = if Self::is_websocket_upgrade(data) {
             ConnectionKind::WebSocket
         } else {
             ConnectionKind::Tcp
+        };
+
+        (kind, Bytes::copy_from_slice(data))
+    }
+
+    /// Terminates TLS if configured, then handles the connection until it is closed
+    async fn run_connection<I>(
+        stream: I,
+        peer_addr: Option<SocketAddr>,
+        context: ConnectionContext<S, C>,
+        connection_state: C,
+    ) where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let Some(acceptor) = context.tls.clone() else {
+            let peer = PeerInfo {
+                addr: peer_addr,
+                identity: None,
+            };
+            return Self::run_decrypted_connection(stream, context, connection_state, peer).await;
+        };
+
+        let stream = tokio::select! {
+            _ = context.shutdown.cancelled() => return,
+            accepted = read_within(context.read_idle_timeout, acceptor.accept(stream)) => match accepted {
+                Some(Ok(s)) => s,
+                Some(Err(e)) => {
+                    warn!("TLS handshake failed: {e}");
+                    return;
+                }
+                None => {
+                    info!("Closing idle connection, the TLS handshake wasn't completed in time");
+                    return;
+                }
+            },
+        };
+
+        let peer = PeerInfo {
+            addr: peer_addr,
+            identity: stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(PeerIdentity::from_certificates),
+        };
+
+        Self::run_decrypted_connection(stream, context, connection_state, peer).await
+    }
+
+    /// Classifies the connection and handles it accordingly, until it is closed
+    async fn run_decrypted_connection<I>(
+        mut stream: I,
+        context: ConnectionContext<S, C>,
+        connection_state: C,
+        peer: PeerInfo,
+    ) where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (kind, prefix) = tokio::select! {
+            _ = context.shutdown.cancelled() => return,
+            classified = read_within(context.read_idle_timeout, Self::classify_connection(&mut stream)) => match classified {
+                Some(classified) => classified,
+                None => {
+                    info!("Closing idle connection, nothing was received for {:?}", context.read_idle_timeout.unwrap_or_default());
+                    return;
+                }
+            },
+        };
+
+        let prefix = match kind {
+            ConnectionKind::WebSocket if !context.upgrade_filter.accepts_all() => {
+                let filtered = tokio::select! {
+                    _ = context.shutdown.cancelled() => return,
+                    filtered = read_within(context.read_idle_timeout, Self::filter_upgrade(&mut stream, prefix, &context)) => filtered,
+                };
+
+                match filtered {
+                    Some(Some(read)) => read,
+                    Some(None) => return,
+                    None => {
+                        info!(
+                            "Closing idle connection, the websocket upgrade request wasn't completed in time"
+                        );
+                        return;
+                    }
+                }
+            }
+            _ => prefix,
+        };
+
+        let stream = PrefixedStream::new(prefix, stream);
+
+        match kind {
+            ConnectionKind::Tcp => {
+                Self::handle_tcp_connection(stream, context, connection_state, peer).await
+            }
+            ConnectionKind::WebSocket => {
+                Self::handle_ws_connection(stream, context, connection_state, peer).await
+            }
+        }
+    }
+
+    /// Checks the upgrade request of a WebSocket connection against the upgrade filter of the
+    /// server, refusing the upgrade if it doesn't pass. Returns all bytes read from the stream,
+    /// or `None` if the upgrade was refused.
+    async fn filter_upgrade<I>(
+        stream: &mut I,
+        prefix: Bytes,
+        context: &ConnectionContext<S, C>,
+    ) -> Option<Bytes>
+    where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (read, upgrade) = match read_upgrade_request(BytesMut::from(&prefix[..]), stream).await
+        {
+            Ok(request) => request,
+            Err(status) => {
+                debug!("Refused malformed websocket upgrade with {status}");
+                refuse_upgrade(stream, status).await;
+                return None;
+            }
+        };
+
+        if let Err(status) = context.upgrade_filter.check(&upgrade).await {
+            debug!(
+                "Refused websocket upgrade to {} with {status}",
+                upgrade.uri()
+            );
+            refuse_upgrade(stream, status).await;
+            return None;
+        }
+
+        Some(read)
+    }
+
+    fn connection_context(
+        &self,
+        shutdown: &CancellationToken,
+        tasks: &TaskTracker,
+    ) -> ConnectionContext<S, C> {
+        ConnectionContext {
+            state: self.state.clone(),
+            handlers: self.handlers.clone(),
+            shutdown: shutdown.clone(),
+            tasks: tasks.clone(),
+            tls: self.options.tls.clone(),
+            upgrade_filter: self.options.upgrade_filter.clone(),
+            hooks: self.hooks.clone(),
+            max_in_flight_requests: self.options.max_in_flight_requests,
+            response_queue_depth: self.options.response_queue_depth,
+            sequential: self.options.sequential.clone(),
+            max_message_size: self.options.max_message_size,
+            read_idle_timeout: self.options.read_idle_timeout,
+            write_timeout: self.options.write_timeout,
+            heartbeat: self.options.heartbeat,
+            codec: Codec::default(),
+            hello: Arc::new(Hello::new(self.options.api_identity.clone())),
+            refuse_incompatible_clients: self.options.refuse_incompatible_clients,
+            reflection: self.options.reflection.clone(),
+            closing: CancellationToken::new(),
         }
     }
 
@@ -405,41 +1731,272 @@ where
     where
         A: ToSocketAddrs,
     {
-        let server = TcpListener::bind(address).await?;
-
-        while let Ok((stream, _)) = server.accept().await {
-            let handlers_clone = self.handlers.clone();
-            let state_clone = self.state.clone();
-            let new_connection_details =
-                ConnectionDetails::new((*self.connection_state_initializer)());
-
-            match Self::classify_connection(&stream).await {
-                ConnectionKind::Tcp => {
-                    tokio::spawn(async move {
-                        Self::handle_tcp_connection(
-                            stream,
-                            state_clone,
-                            new_connection_details,
-                            handlers_clone,
-                        )
-                        .await;
-                    });
-                }
-                ConnectionKind::WebSocket => {
-                    tokio::spawn(async move {
-                        Self::handle_ws_connection(
-                            stream,
-                            state_clone,
-                            new_connection_details,
-                            handlers_clone,
-                        )
-                        .await;
-                    });
-                }
+        self.serve_with_shutdown(address, future::pending()).await
+    }
+
+    /// Serves until the `signal` future completes, after which the server shuts down gracefully:
+    /// it stops accepting connections and new requests, waits for the in-flight requests to
+    /// be responded to and closes the connections.
+    ///
+    /// Waiting for in-flight requests is limited by the shutdown timeout of the server,
+    /// see `ServerBuilder::with_shutdown_timeout`.
+    ///
+    /// # Error
+    /// When the listener fails for good, after shutting down the same way. Errors accepting
+    /// a single connection, or running out of file descriptors for a moment, are only logged.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use lirpc::ServerBuilder;
+    /// # async fn run() {
+    /// let server = ServerBuilder::new().build();
+    ///
+    /// server
+    ///     .serve_with_shutdown("127.0.0.1:5000", async {
+    ///         tokio::signal::ctrl_c().await.ok();
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn serve_with_shutdown<A>(
+        &self,
+        address: A,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), LiRpcError>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(address).await?;
+
+        self.serve_listener_with_shutdown(listener, signal).await
+    }
+
+    /// Serves on an already bound listener, e.g. one passed through socket activation
+    /// or one bound to port 0 of which the assigned port has to be known.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use lirpc::ServerBuilder;
+    /// # use tokio::net::TcpListener;
+    /// # async fn run() {
+    /// let server = ServerBuilder::new().build();
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let address = listener.local_addr().unwrap();
+    ///
+    /// println!("Serving on {address}");
+    ///
+    /// server.serve_listener(listener).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn serve_listener(&self, listener: TcpListener) -> Result<(), LiRpcError> {
+        self.serve_listener_with_shutdown(listener, future::pending())
+            .await
+    }
+
+    /// Combination of `serve_listener` and `serve_with_shutdown`
+    pub async fn serve_listener_with_shutdown(
+        &self,
+        listener: TcpListener,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), LiRpcError> {
+        self.serve_incoming(listener, Self::run_connection, signal)
+            .await
+    }
+
+    /// Serves on a Unix domain socket at `path`, using the same length-delimited
+    /// framing as plain TCP connections. WebSocket and TLS are not supported on it.
+    ///
+    /// A stale socket file at `path` (e.g. left behind by a crashed server) is replaced,
+    /// and the socket file is removed again once the server stops. If a server is still
+    /// listening on `path`, this fails with `AddrInUse` instead.
+    /// See `ServerBuilder::with_unix_socket_permissions` for restricting access to it.
+    #[cfg(unix)]
+    pub async fn serve_unix(&self, path: impl AsRef<Path>) -> Result<(), LiRpcError> {
+        self.serve_unix_with_shutdown(path, future::pending()).await
+    }
+
+    /// Combination of `serve_unix` and `serve_with_shutdown`
+    #[cfg(unix)]
+    pub async fn serve_unix_with_shutdown(
+        &self,
+        path: impl AsRef<Path>,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), LiRpcError> {
+        let path = path.as_ref();
+
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            // Only stale sockets are replaced, not the one of a server that is still running
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::from(io::ErrorKind::AddrInUse).into());
             }
+            fs::remove_file(path)?;
         }
 
-        Ok(())
+        let listener = match self.options.unix_socket_permissions {
+            Some(mode) => Self::bind_unix_with_permissions(path, mode)?,
+            None => UnixListener::bind(path)?,
+        };
+
+        let served = self
+            .serve_incoming(
+                listener,
+                |stream, _, context, connection_state| {
+                    Self::handle_tcp_connection(
+                        stream,
+                        context,
+                        connection_state,
+                        PeerInfo::default(),
+                    )
+                },
+                signal,
+            )
+            .await;
+
+        // Someone else could have removed the socket file already
+        let removed = match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+
+        served?;
+        Ok(removed?)
+    }
+
+    /// Binds the socket in a directory only the server can access, and only moves it to
+    /// `path` once it has its permissions, so no one can connect to it before
+    #[cfg(unix)]
+    fn bind_unix_with_permissions(path: &Path, mode: u32) -> io::Result<UnixListener> {
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let private = parent.join(format!(".{file_name}.{}", std::process::id()));
+
+        fs::DirBuilder::new().mode(0o700).create(&private)?;
+        let staged = private.join("socket");
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+            fs::rename(&staged, path)?;
+
+            Ok(listener)
+        });
+        fs::remove_dir_all(&private)?;
+
+        bound
+    }
+
+    /// Accepts connections until `signal` completes, handling each with `handle`.
+    /// Then shuts down gracefully, see `serve_with_shutdown`. Errors accepting a single
+    /// connection are skipped, if the listener itself fails it shuts down as well.
+    async fn serve_incoming<L, H, F>(
+        &self,
+        listener: L,
+        handle: H,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), LiRpcError>
+    where
+        L: Listener,
+        H: Fn(L::Stream, Option<SocketAddr>, ConnectionContext<S, C>, C) -> F,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = CancellationToken::new();
+        let tasks = TaskTracker::new();
+        let connection_slots = self
+            .options
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+
+        tokio::pin!(signal);
+        let mut failed = None;
+
+        loop {
+            // Stop accepting connections while the maximum is reached
+            let slot = match &connection_slots {
+                Some(slots) => tokio::select! {
+                    _ = &mut signal => break,
+                    // The semaphore is never closed
+                    slot = slots.clone().acquire_owned() => slot.ok(),
+                },
+                None => None,
+            };
+
+            let (stream, peer_addr) = tokio::select! {
+                _ = &mut signal => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => match AcceptError::classify(&e) {
+                        AcceptError::Connection => {
+                            debug!("Error accepting connection: {e}");
+                            continue;
+                        }
+                        AcceptError::Transient => {
+                            warn!("Error accepting connection, retrying in {ACCEPT_BACKOFF:?}: {e}");
+                            tokio::select! {
+                                _ = &mut signal => break,
+                                _ = tokio::time::sleep(ACCEPT_BACKOFF) => continue,
+                            }
+                        }
+                        AcceptError::Fatal => {
+                            error!("Error accepting connections, shutting down: {e}");
+                            failed = Some(e);
+                            break;
+                        }
+                    },
+                },
+            };
+
+            let context = self.connection_context(&shutdown, &tasks);
+            let connection_state = (*self.connection_state_initializer)();
+
+            let connection = handle(stream, peer_addr, context, connection_state);
+            tasks.spawn(async move {
+                connection.await;
+                drop(slot);
+            });
+        }
+
+        drop(listener);
+
+        info!("Shutting down, waiting for in-flight requests to finish");
+
+        shutdown.cancel();
+        tasks.close();
+
+        if tokio::time::timeout(self.options.shutdown_timeout, tasks.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "Shutdown timeout reached with {} tasks still running",
+                tasks.len()
+            );
+        }
+
+        match failed {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Serves a single, already established connection over any kind of stream.
+    /// Both plain TCP and WebSocket clients are accepted, just like with `serve`,
+    /// and TLS is terminated if it is configured on the server.
+    ///
+    /// Returns once the connection is closed.
+    pub async fn serve_connection<I>(&self, stream: I)
+    where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let tasks = TaskTracker::new();
+        let context = self.connection_context(&CancellationToken::new(), &tasks);
+        let connection_state = (*self.connection_state_initializer)();
+
+        Self::run_connection(stream, None, context, connection_state).await;
     }
 
     pub fn compile_api_spec(
@@ -452,7 +2009,12 @@ where
             version,
             self.handlers
                 .iter()
-                .map(|h| (h.0.to_string(), h.1.get_spec()))
+                .map(|(name, handler)| {
+                    let mut spec = handler.get_spec();
+                    spec.notification = self.options.notifications.contains(name);
+
+                    (name.to_string(), spec)
+                })
                 .collect(),
             (*self.type_definitions).clone(),
         )
@@ -470,11 +2032,18 @@ where
     }
 }
 
-enum ConnectionKind {
-    Tcp,
-    WebSocket,
+/// The message a panic was started with, if it was a string
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
 }
 
+#[cfg(test)]
+mod tests;
+
 #[derive(thiserror::Error, Debug)]
 pub enum ApiSpecCompilationError {
     #[error(
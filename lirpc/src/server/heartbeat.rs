@@ -0,0 +1,78 @@
+use std::{future, time::Duration};
+
+use tokio::time::Instant;
+
+/// Reserved method clients call to check the connection is alive,
+/// it is answered right away with an empty response
+pub(crate) const PING_METHOD: &str = "lirpc_ping";
+
+/// See `ServerBuilder::with_heartbeat`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HeartbeatOptions {
+    pub(crate) interval: Duration,
+    pub(crate) timeout: Duration,
+}
+
+/// What to do once the heartbeat is due
+pub(crate) enum Beat {
+    /// Nothing was received for the interval, ask the client for a sign of life
+    Ping,
+    /// Nothing was received since the ping, the client is gone
+    Missed,
+}
+
+/// Tracks when the client last showed a sign of life
+pub(crate) struct Heartbeat {
+    options: Option<HeartbeatOptions>,
+    deadline: Instant,
+    pinged: bool,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(options: Option<HeartbeatOptions>) -> Self {
+        let mut heartbeat = Self {
+            options,
+            deadline: Instant::now(),
+            pinged: false,
+        };
+        heartbeat.received();
+
+        heartbeat
+    }
+
+    /// Something was received from the client, so it is alive
+    pub(crate) fn received(&mut self) {
+        if let Some(options) = self.options {
+            self.deadline = Instant::now() + options.interval;
+            self.pinged = false;
+        }
+    }
+
+    /// Completes once the heartbeat is due, never without a heartbeat
+    pub(crate) async fn due(&self) {
+        match self.options {
+            Some(_) => tokio::time::sleep_until(self.deadline).await,
+            None => future::pending().await,
+        }
+    }
+
+    /// Called once the heartbeat is due
+    pub(crate) fn beat(&mut self) -> Beat {
+        let Some(options) = self.options else {
+            return Beat::Ping;
+        };
+
+        if self.pinged {
+            return Beat::Missed;
+        }
+
+        self.pinged = true;
+        self.deadline = Instant::now() + options.timeout;
+
+        Beat::Ping
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.options.map(|o| o.timeout).unwrap_or_default()
+    }
+}
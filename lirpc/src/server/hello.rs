@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{api_spec::ApiIdentity, lirpc_message::PROTOCOL_VERSION};
+
+/// Reserved method clients call right after connecting, to find out whether
+/// they are compatible with the server
+pub(crate) const HELLO_METHOD: &str = "lirpc_hello";
+
+/// The payload of the hello of the client, and of the answer of the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Hello {
+    pub(crate) protocol_version: u32,
+    /// Unknown to servers without `ServerBuilder::with_api_identity`,
+    /// and to clients that weren't generated from an api spec
+    #[serde(default)]
+    pub(crate) api: Option<ApiIdentity>,
+}
+
+impl Hello {
+    pub(crate) fn new(api: Option<ApiIdentity>) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            api,
+        }
+    }
+
+    /// Why the client that said `self` is incompatible with the server that says `server`
+    pub(crate) fn incompatibility(&self, server: &Hello) -> Option<String> {
+        if self.protocol_version != server.protocol_version {
+            return Some(format!(
+                "the client speaks protocol version {}, the server {}",
+                self.protocol_version, server.protocol_version
+            ));
+        }
+
+        let (Some(client), Some(server)) = (&self.api, &server.api) else {
+            return None;
+        };
+
+        if client.name != server.name {
+            return Some(format!(
+                "the client was generated for api {}, the server serves {}",
+                client.name, server.name
+            ));
+        }
+
+        if client.fingerprint != server.fingerprint {
+            return Some(format!(
+                "the client was generated from another spec of {} (version {}), the server serves version {}",
+                server.name, client.version, server.version
+            ));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{api_spec::ApiIdentity, server::hello::Hello};
+
+    fn identity(name: &str, fingerprint: &str) -> Option<ApiIdentity> {
+        Some(ApiIdentity {
+            name: name.to_string(),
+            version: "0.1.0".to_string(),
+            fingerprint: fingerprint.to_string(),
+        })
+    }
+
+    #[test]
+    fn should_find_incompatibilities() {
+        let server = Hello::new(identity("greeter", "aaaa"));
+
+        assert!(
+            Hello::new(identity("greeter", "aaaa"))
+                .incompatibility(&server)
+                .is_none()
+        );
+        assert!(Hello::new(None).incompatibility(&server).is_none());
+        assert!(
+            Hello::new(identity("greeter", "bbbb"))
+                .incompatibility(&server)
+                .is_some()
+        );
+        assert!(
+            Hello::new(identity("auth", "aaaa"))
+                .incompatibility(&server)
+                .is_some()
+        );
+
+        let old_protocol = Hello {
+            protocol_version: 0,
+            api: None,
+        };
+        assert!(old_protocol.incompatibility(&server).is_some());
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore, oneshot},
+    task::AbortHandle,
+};
+use tracing::debug;
+
+use crate::{error::LiRpcProtocolError, lirpc_message::LiRpcRequest};
+
+/// Reserved method clients call to cancel one of their requests, the `id`
+/// in the headers of the cancellation is the id of the request to cancel
+pub(crate) const CANCEL_METHOD: &str = "lirpc_cancel";
+
+/// Which requests are handled one after the other, in the order they were read
+#[derive(Default)]
+pub(crate) struct Sequential {
+    pub(crate) all: bool,
+    pub(crate) methods: HashSet<String>,
+}
+
+impl Sequential {
+    pub(crate) fn applies_to(&self, method: &str) -> bool {
+        self.all || self.methods.contains(method)
+    }
+}
+
+/// The handlers running on a single connection by request id, so they can be cancelled
+#[derive(Clone)]
+pub(crate) struct InFlightRequests {
+    tasks: Arc<Mutex<HashMap<u32, AbortHandle>>>,
+    /// Limits the number of requests handled at the same time, if set
+    limit: Option<Arc<Semaphore>>,
+    /// Completes once the last request that has to be handled sequentially is done
+    last_in_sequence: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+}
+
+/// The place of a request in the sequence of requests handled one after the other,
+/// the next request gets its turn once this one is dropped
+pub(crate) struct Turn {
+    previous: Option<oneshot::Receiver<()>>,
+    _done: oneshot::Sender<()>,
+}
+
+impl Turn {
+    /// Waits until the previous request in the sequence is done
+    pub(crate) async fn wait(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            // Either sent or dropped, both mean the previous request is done
+            previous.await.ok();
+        }
+    }
+}
+
+/// Room for one more request on the connection, held until the request is handled
+pub(crate) struct InFlightPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl InFlightRequests {
+    pub(crate) fn new(max_in_flight_requests: Option<usize>) -> Self {
+        Self {
+            tasks: Arc::default(),
+            limit: max_in_flight_requests.map(|max| Arc::new(Semaphore::new(max))),
+            last_in_sequence: Arc::default(),
+        }
+    }
+
+    /// Puts a request at the end of the sequence. Has to be called in the order
+    /// the requests were read.
+    pub(crate) fn take_turn(&self) -> Turn {
+        let (done, receiver) = oneshot::channel();
+        let previous = self.last_in_sequence.lock().unwrap().replace(receiver);
+
+        Turn {
+            previous,
+            _done: done,
+        }
+    }
+
+    /// Waits until there is room for one more request
+    pub(crate) async fn reserve(&self) -> InFlightPermit {
+        let permit = match &self.limit {
+            // The semaphore is never closed
+            Some(limit) => limit.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+
+        InFlightPermit { _permit: permit }
+    }
+
+    /// Registers the task handling request `id`, which is spawned by `spawn`.
+    /// The task has to call `finish` once it is done.
+    pub(crate) fn track(&self, id: u32, spawn: impl FnOnce() -> AbortHandle) {
+        // Holding the lock while spawning, so the task can't finish before it is registered
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.insert(id, spawn());
+    }
+
+    /// Called by the task handling request `id` once it is done
+    pub(crate) fn finish(&self, id: u32) {
+        let mut tasks = self.tasks.lock().unwrap();
+
+        // The client could have reused the id for a newer request
+        if tasks.get(&id).map(AbortHandle::id) == tokio::task::try_id() {
+            tasks.remove(&id);
+        }
+    }
+
+    /// Aborts the handling of request `id`, returns whether it was still running
+    pub(crate) fn cancel(&self, id: u32) -> bool {
+        let task = self.tasks.lock().unwrap().remove(&id);
+
+        match task {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A request read from the connection, or the error it couldn't be parsed with
+pub(crate) type ReadRequest = Result<LiRpcRequest, (Option<u32>, LiRpcProtocolError)>;
+
+/// The requests read from a connection that wait for room to be handled. The connection is
+/// read from while the backlog has room, so cancellations get through while it is busy.
+pub(crate) struct Backlog {
+    requests: VecDeque<ReadRequest>,
+    capacity: usize,
+}
+
+impl Backlog {
+    /// Holds as many requests as can be in flight, or a single one without a limit
+    pub(crate) fn new(max_in_flight_requests: Option<usize>) -> Self {
+        Self {
+            requests: VecDeque::new(),
+            capacity: max_in_flight_requests.unwrap_or(1),
+        }
+    }
+
+    /// Whether another message can be read from the connection
+    pub(crate) fn has_room(&self) -> bool {
+        self.requests.len() < self.capacity
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// The next request to handle, in the order they were read
+    pub(crate) fn pop(&mut self) -> Option<ReadRequest> {
+        self.requests.pop_front()
+    }
+
+    /// Queues a request read from the connection. Cancellations don't wait for room,
+    /// the request they target is cancelled right away, whether it runs or waits.
+    pub(crate) fn push(&mut self, request: ReadRequest, in_flight: &InFlightRequests) {
+        let cancelled = match &request {
+            Ok(request) if request.headers.function == CANCEL_METHOD => request.headers.id,
+            _ => {
+                self.requests.push_back(request);
+                return;
+            }
+        };
+
+        let waiting = self.requests.iter().position(|request| {
+            matches!(request, Ok(request) if request.headers.id == cancelled && !request.headers.notification)
+        });
+        let removed = waiting
+            .and_then(|index| self.requests.remove(index))
+            .is_some();
+
+        if removed || in_flight.cancel(cancelled) {
+            debug!("Cancelled message ({cancelled})");
+        }
+    }
+}
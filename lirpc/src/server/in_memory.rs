@@ -0,0 +1,57 @@
+use bytes::Bytes;
+use lirpc_rs_client::{Client, error::Error, transport::tcp::Tcp};
+use tokio::io::DuplexStream;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::server::{PeerInfo, Server};
+
+/// Client connected to a server through `Server::connect_in_memory`
+pub type InMemoryClient = Client<Tcp<DuplexStream>, Bytes>;
+
+/// Bytes that can be buffered in each direction of an in-memory connection
+const IN_MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+impl<S, C> Server<S, C>
+where
+    S: Clone + Send + Sync + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    /// Connects a client to this server without any sockets, for (integration) testing handlers.
+    /// The connection is handled exactly like one over plain TCP, with its own connection state.
+    ///
+    /// Available with the `in-memory` feature.
+    ///
+    /// # Example
+    /// ```rs
+    /// #[tokio::test]
+    /// async fn should_greet() {
+    ///     let server = ServerBuilder::new().with_handlers(handlers!(greet)).build();
+    ///
+    ///     let mut client = server.connect_in_memory().await.unwrap();
+    ///     let response = client
+    ///         .call::<(), String>("greet".to_string(), None)
+    ///         .await
+    ///         .unwrap()
+    ///         .resolve()
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     assert_eq!(response, "hello");
+    /// }
+    /// ```
+    pub async fn connect_in_memory(&self) -> Result<InMemoryClient, Error> {
+        let (client_stream, server_stream) = tokio::io::duplex(IN_MEMORY_BUFFER_SIZE);
+
+        let context = self.connection_context(&CancellationToken::new(), &TaskTracker::new());
+        let connection_state = (*self.connection_state_initializer)();
+
+        tokio::spawn(Self::handle_tcp_connection(
+            server_stream,
+            context,
+            connection_state,
+            PeerInfo::default(),
+        ));
+
+        Client::new_tcp_with_stream(client_stream).await
+    }
+}
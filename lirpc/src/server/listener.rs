@@ -0,0 +1,73 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Something the server accepts connections on
+pub(crate) trait Listener {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Accepts a connection, together with the address of the peer if it has one
+    fn accept(&self)
+    -> impl Future<Output = io::Result<(Self::Stream, Option<SocketAddr>)>> + Send;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, Option<SocketAddr>)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((stream, Some(addr)))
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, Option<SocketAddr>)> {
+        let (stream, _) = UnixListener::accept(self).await?;
+        Ok((stream, None))
+    }
+}
+
+/// How long to wait before accepting again after an error that may pass
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What an error accepting a connection means for the listener
+pub(crate) enum AcceptError {
+    /// Only the connection being accepted failed, e.g. because the peer went away
+    Connection,
+    /// Accepting failed for a reason that may pass, e.g. the process ran out of
+    /// file descriptors, which are freed once connections are closed
+    Transient,
+    /// The listener can't accept connections anymore, e.g. because it was closed
+    Fatal,
+}
+
+impl AcceptError {
+    pub(crate) fn classify(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted => Self::Connection,
+            // EINVAL, the socket isn't listening
+            io::ErrorKind::InvalidInput => Self::Fatal,
+            #[cfg(unix)]
+            _ if matches!(
+                e.raw_os_error(),
+                Some(libc::EBADF | libc::ENOTSOCK | libc::EOPNOTSUPP)
+            ) =>
+            {
+                Self::Fatal
+            }
+            _ => Self::Transient,
+        }
+    }
+}
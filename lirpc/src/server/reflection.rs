@@ -0,0 +1,33 @@
+use serde_json::Value;
+
+use crate::api_spec::ApiSpec;
+
+/// Reserved method responding with the compiled api spec of the server
+pub(crate) const SPEC_METHOD: &str = "lirpc_spec";
+/// Reserved method responding with the names of the methods of the server
+pub(crate) const METHODS_METHOD: &str = "lirpc_methods";
+
+/// The answers to the reflection methods, see `ServerBuilder::with_reflection`.
+/// Serialized once, as the spec doesn't change while serving.
+pub(crate) struct Reflection {
+    spec: Value,
+    methods: Value,
+}
+
+impl Reflection {
+    pub(crate) fn new(spec: &ApiSpec) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            spec: serde_json::to_value(spec)?,
+            methods: serde_json::to_value(spec.methods.keys().collect::<Vec<_>>())?,
+        })
+    }
+
+    /// The payload to respond with, if `method` is a reflection method
+    pub(crate) fn answer(&self, method: &str) -> Option<&Value> {
+        match method {
+            SPEC_METHOD => Some(&self.spec),
+            METHODS_METHOD => Some(&self.methods),
+            _ => None,
+        }
+    }
+}
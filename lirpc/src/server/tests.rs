@@ -0,0 +1,2504 @@
+use std::{
+    future,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use lirpc_rs_client::{
+    Client, ConnectionOptions,
+    error::{Error as ClientError, ProtocolError},
+};
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedIssuer, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use rustls::{
+    ClientConfig, RootCertStore, ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject},
+};
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::{
+    client_async, connect_async,
+    tungstenite::{Message, client::IntoClientRequest, http::StatusCode},
+};
+use tokio_util::codec::LengthDelimitedCodec;
+
+use crate::{
+    ClientAuth, ConnectionDetails, ServerBuilder,
+    error::{LiRpcError, LiRpcProtocolError},
+    extractors::{ConnectionState, OutputStream, PeerAddr, PeerIdentity, State, UpgradeHeaders},
+    handlers,
+    lifecycle::{DisconnectReason, Reject},
+    lirpc_message::{LiRpcRequest, LiRpcResponse},
+    middleware::Next,
+    server::{
+        Server,
+        listener::{ACCEPT_BACKOFF, Listener},
+    },
+};
+
+async fn slow() -> String {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    "done".to_string()
+}
+
+async fn never_returns() {
+    future::pending::<()>().await
+}
+
+async fn greet() -> String {
+    "hello".to_string()
+}
+
+async fn large_response() -> String {
+    "x".repeat(1024)
+}
+
+async fn count(ConnectionState(counter): ConnectionState<Arc<AtomicU32>>) -> u32 {
+    counter.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+async fn session(ConnectionState(session): ConnectionState<String>) -> String {
+    session
+}
+
+async fn upgrade_info(PeerAddr(addr): PeerAddr, upgrade: UpgradeHeaders) -> String {
+    format!(
+        "{} {} {:?}",
+        addr.ip(),
+        upgrade.uri(),
+        upgrade.headers().get("authorization")
+    )
+}
+
+async fn whoami(identity: PeerIdentity) -> Vec<u8> {
+    identity.certificate().to_vec()
+}
+
+async fn panics() -> String {
+    panic!("handler went wrong")
+}
+
+async fn count_to_three(output: OutputStream<u32>) -> Result<(), String> {
+    for i in 1..=3 {
+        output.send(i).await.ok();
+    }
+    Ok(())
+}
+
+async fn gives_up_after_two(output: OutputStream<u32>) -> Result<(), String> {
+    for i in 1..=2 {
+        output.send(i).await.ok();
+    }
+    Err("gave up".to_string())
+}
+
+async fn stalled_stream(output: OutputStream<u32>) {
+    output.send(1).await.ok();
+    future::pending::<()>().await
+}
+
+/// Reports through the channel when it is dropped
+struct DropSignal(mpsc::UnboundedSender<&'static str>);
+
+impl Drop for DropSignal {
+    fn drop(&mut self) {
+        self.0.send("dropped").ok();
+    }
+}
+
+async fn cancellable(State(events): State<mpsc::UnboundedSender<&'static str>>) {
+    events.send("started").ok();
+    let _signal = DropSignal(events);
+    future::pending::<()>().await
+}
+
+async fn record(State(events): State<mpsc::UnboundedSender<&'static str>>) {
+    events.send("recorded").ok();
+}
+
+/// Waits for the next event of the `cancellable` handler
+async fn next_event(events: &mut mpsc::UnboundedReceiver<&'static str>) -> &'static str {
+    tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn should_finish_in_flight_requests_on_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    let server = tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(slow))
+            .build()
+            .serve_listener_with_shutdown(listener, async {
+                shutdown_rx.await.ok();
+            })
+            .await
+    });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    let call = client
+        .call::<(), String>("slow".to_string(), None)
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    shutdown_tx.send(()).unwrap();
+
+    assert_eq!(call.resolve().await.unwrap(), "done");
+    server.await.unwrap().unwrap();
+
+    assert!(Client::new_tcp_plain(address).await.is_err());
+}
+
+#[tokio::test]
+async fn should_stop_waiting_after_shutdown_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    let server = tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(never_returns))
+            .with_shutdown_timeout(Duration::from_millis(100))
+            .build()
+            .serve_listener_with_shutdown(listener, async {
+                shutdown_rx.await.ok();
+            })
+            .await
+    });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    let _call = client
+        .call::<(), ()>("never_returns".to_string(), None)
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let shutdown_started = Instant::now();
+    shutdown_tx.send(()).unwrap();
+
+    server.await.unwrap().unwrap();
+
+    assert!(shutdown_started.elapsed() < Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn should_serve_listener_bound_to_port_zero() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .build()
+            .serve_listener(listener)
+            .await
+    });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+
+    assert_eq!(response, "hello");
+}
+
+/// Fails to accept connections with the given errors, then waits for connections forever
+struct FailingListener(std::sync::Mutex<Vec<std::io::Error>>);
+
+impl Listener for FailingListener {
+    type Stream = tokio::io::DuplexStream;
+
+    async fn accept(&self) -> std::io::Result<(Self::Stream, Option<SocketAddr>)> {
+        let error = self.0.lock().unwrap().pop();
+        match error {
+            Some(e) => Err(e),
+            None => future::pending().await,
+        }
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_skip_transient_accept_errors_and_fail_on_fatal_ones() {
+    let server = ServerBuilder::new().build();
+
+    // Popped from the back
+    let listener = FailingListener(std::sync::Mutex::new(vec![
+        std::io::Error::from_raw_os_error(libc::EBADF),
+        std::io::Error::from_raw_os_error(libc::ENOBUFS),
+        std::io::Error::from_raw_os_error(libc::EMFILE),
+        std::io::Error::from(std::io::ErrorKind::ConnectionAborted),
+    ]));
+    let started = Instant::now();
+    let served = server
+        .serve_incoming(listener, Server::run_connection, future::pending())
+        .await;
+
+    assert!(matches!(served, Err(LiRpcError::IoError(e)) if e.raw_os_error() == Some(libc::EBADF)));
+    assert!(started.elapsed() >= 2 * ACCEPT_BACKOFF);
+}
+
+#[tokio::test]
+async fn should_serve_connection_over_any_stream() {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .build()
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+/// Writes the given PEM files to a fresh temporary directory
+fn write_pem_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lirpc-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file_name, contents) in files {
+        std::fs::write(dir.join(file_name), contents).unwrap();
+    }
+
+    dir
+}
+
+/// Generates a self-signed certificate for `localhost`, returning it and its key as PEM
+/// together with a connector that trusts it
+fn self_signed_certificate() -> (String, String, TlsConnector) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    (
+        certified.cert.pem(),
+        certified.signing_key.serialize_pem(),
+        TlsConnector::from(Arc::new(client_config)),
+    )
+}
+
+#[tokio::test]
+async fn should_terminate_tls_with_certificate_from_pem_files() {
+    let (cert_pem, key_pem, connector) = self_signed_certificate();
+
+    let dir = write_pem_files("tls", &[("cert.pem", &cert_pem), ("key.pem", &key_pem)]);
+
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_tls_pem_files(dir.join("cert.pem"), dir.join("key.pem"))
+        .unwrap()
+        .build();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let stream = TcpStream::connect(address).await.unwrap();
+    let stream = connector
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(stream);
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_terminate_tls_for_websocket_clients() {
+    let (cert_pem, key_pem, connector) = self_signed_certificate();
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from_pem_slice(cert_pem.as_bytes()).unwrap()],
+            PrivateKeyDer::from_pem_slice(key_pem.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_tls_config(config)
+            .build()
+            .serve_listener(listener)
+            .await
+    });
+
+    let stream = TcpStream::connect(address).await.unwrap();
+    let stream = connector
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    let (mut socket, _) = client_async("wss://localhost/", stream).await.unwrap();
+
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    socket
+        .send(Message::text(request.to_string()))
+        .await
+        .unwrap();
+
+    let response = socket.next().await.unwrap().unwrap();
+    let response: Value = serde_json::from_str(response.to_text().unwrap()).unwrap();
+
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+/// A server set up with mutual TLS, together with what a client needs to connect to it
+struct MutualTls {
+    server: Server<(), ()>,
+    /// Trusts the certificate of the server
+    roots: RootCertStore,
+    /// Signed by the CA the server verifies client certificates against
+    client_cert: CertificateDer<'static>,
+    client_key: PrivateKeyDer<'static>,
+}
+
+fn mutual_tls_server(name: &str, client_auth: ClientAuth) -> MutualTls {
+    let server_certified =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+    let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+    client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client_key = KeyPair::generate().unwrap();
+    let client_cert = client_params.signed_by(&client_key, &ca).unwrap();
+
+    let dir = write_pem_files(
+        name,
+        &[
+            ("cert.pem", &server_certified.cert.pem()),
+            ("key.pem", &server_certified.signing_key.serialize_pem()),
+            ("client-ca.pem", &ca.pem()),
+        ],
+    );
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(whoami))
+        .with_mutual_tls_pem_files(
+            dir.join("cert.pem"),
+            dir.join("key.pem"),
+            dir.join("client-ca.pem"),
+            client_auth,
+        )
+        .unwrap()
+        .build();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(server_certified.cert.der().clone()).unwrap();
+
+    MutualTls {
+        server,
+        roots,
+        client_cert: client_cert.der().clone(),
+        client_key: PrivateKeyDer::Pkcs8(client_key.serialize_der().into()),
+    }
+}
+
+#[tokio::test]
+async fn should_expose_identity_of_client_with_certificate() {
+    let MutualTls {
+        server,
+        roots,
+        client_cert,
+        client_key,
+    } = mutual_tls_server("mtls-identity", ClientAuth::Required);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(vec![client_cert.clone()], client_key)
+        .unwrap();
+    let mut client = Client::new_tcp_tls_with_config(format!("localhost:{port}"), config)
+        .await
+        .unwrap();
+
+    let response = client
+        .call::<(), Vec<u8>>("whoami".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+
+    assert_eq!(response, client_cert.to_vec());
+}
+
+#[tokio::test]
+async fn should_refuse_client_without_certificate_when_required() {
+    let MutualTls { server, roots, .. } = mutual_tls_server("mtls-required", ClientAuth::Required);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TcpStream::connect(address).await.unwrap();
+    // With TLS 1.3 the client considers the handshake done before the server
+    // verified its certificate, the refusal only shows once reading
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(stream);
+    let request = json!({"headers": {"id": 1, "function": "whoami"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    assert!(!matches!(framed.next().await, Some(Ok(_))));
+}
+
+#[tokio::test]
+async fn should_reject_peer_identity_of_client_without_certificate_when_optional() {
+    let MutualTls { server, roots, .. } = mutual_tls_server("mtls-optional", ClientAuth::Optional);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let mut client = Client::new_tcp_tls_with_config(format!("localhost:{port}"), config)
+        .await
+        .unwrap();
+
+    let response = client
+        .call::<(), Vec<u8>>("whoami".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::ExtractorRejection(_)))
+    ));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_serve_over_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("lirpc-unix-{}.sock", std::process::id()));
+    // A socket file left behind by an earlier run is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server_path = path.clone();
+    let server = tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_unix_socket_permissions(0o600)
+            .build()
+            .serve_unix_with_shutdown(server_path, async {
+                shutdown_rx.await.ok();
+            })
+            .await
+    });
+
+    let mut client = loop {
+        match Client::new_unix(&path).await {
+            Ok(client) => break client,
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+
+    assert_eq!(response, "hello");
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_not_replace_unix_socket_of_running_server() {
+    let path = std::env::temp_dir().join(format!("lirpc-running-{}.sock", std::process::id()));
+    let running = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+    let served = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .build()
+        .serve_unix(&path)
+        .await;
+
+    assert!(
+        matches!(served, Err(LiRpcError::IoError(e)) if e.kind() == std::io::ErrorKind::AddrInUse)
+    );
+    assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+
+    drop(running);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_stop_serving_unix_socket_removed_by_someone_else() {
+    let path = std::env::temp_dir().join(format!("lirpc-removed-{}.sock", std::process::id()));
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server_path = path.clone();
+    let server = tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_unix_socket_permissions(0o600)
+            .build()
+            .serve_unix_with_shutdown(server_path, async {
+                shutdown_rx.await.ok();
+            })
+            .await
+    });
+
+    while !path.exists() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn should_give_each_in_memory_client_its_own_connection() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(count))
+        .build_with_connection_state(|| Arc::new(AtomicU32::new(0)));
+
+    let mut first = server.connect_in_memory().await.unwrap();
+    let mut second = server.connect_in_memory().await.unwrap();
+
+    for expected in [1, 2] {
+        let response = first
+            .call::<(), u32>("count".to_string(), None)
+            .await
+            .unwrap()
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(response, expected);
+    }
+
+    let response = second
+        .call::<(), u32>("count".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, 1);
+}
+
+#[tokio::test]
+async fn should_respond_with_protocol_errors_in_memory() {
+    let server = ServerBuilder::new().with_handlers(handlers!(greet)).build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+    let response = client
+        .call::<(), String>("unknown".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::UnknownMethod(method))) if method == "unknown"
+    ));
+}
+
+#[tokio::test]
+async fn should_run_lifecycle_hooks_around_connection() {
+    let (disconnected_tx, mut disconnected_rx) = mpsc::unbounded_channel();
+
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(session))
+        .on_connect(|_, session: &mut String, handshake| {
+            Box::pin(async move {
+                *session = format!("{:?} session", handshake.kind);
+                Ok(())
+            })
+        })
+        .on_disconnect(move |session, reason| {
+            let disconnected_tx = disconnected_tx.clone();
+            let session = session.clone();
+            Box::pin(async move {
+                disconnected_tx.send((session, reason)).unwrap();
+            })
+        })
+        .build_with_connection_state(String::new);
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let connection = tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    let request = json!({"headers": {"id": 1, "function": "session"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(
+        response,
+        json!({"headers": {"id": 1}, "payload": "Tcp session"})
+    );
+
+    drop(framed);
+    connection.await.unwrap();
+
+    assert_eq!(
+        disconnected_rx.recv().await.unwrap(),
+        ("Tcp session".to_string(), DisconnectReason::Closed)
+    );
+}
+
+#[tokio::test]
+async fn should_close_connection_rejected_on_connect() {
+    let (disconnected_tx, mut disconnected_rx) = mpsc::unbounded_channel::<()>();
+
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .on_connect(|_, _, _| Box::pin(async { Err(Reject::new("not welcome")) }))
+        .on_disconnect(move |_, _| {
+            let disconnected_tx = disconnected_tx.clone();
+            Box::pin(async move {
+                disconnected_tx.send(()).unwrap();
+            })
+        })
+        .build();
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let connection = tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    assert!(framed.next().await.is_none());
+    connection.await.unwrap();
+
+    // The connection was never accepted, so it isn't disconnected either
+    assert!(disconnected_rx.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn should_expose_upgrade_request_and_peer_address_to_handlers() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(upgrade_info))
+            .build()
+            .serve_listener(listener)
+            .await
+    });
+
+    let mut upgrade_request = format!("ws://{address}/rpc?token=abc")
+        .into_client_request()
+        .unwrap();
+    upgrade_request
+        .headers_mut()
+        .insert("authorization", "Bearer abc".parse().unwrap());
+    let (mut socket, _) = connect_async(upgrade_request).await.unwrap();
+
+    let request = json!({"headers": {"id": 1, "function": "upgrade_info"}, "payload": null});
+    socket
+        .send(Message::text(request.to_string()))
+        .await
+        .unwrap();
+
+    let response = socket.next().await.unwrap().unwrap();
+    let response: Value = serde_json::from_str(response.to_text().unwrap()).unwrap();
+
+    assert_eq!(
+        response,
+        json!({"headers": {"id": 1}, "payload": "127.0.0.1 /rpc?token=abc Some(\"Bearer abc\")"})
+    );
+}
+
+#[tokio::test]
+async fn should_reject_upgrade_headers_outside_of_websocket_connections() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(upgrade_info))
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+    let response = client
+        .call::<(), String>("upgrade_info".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::ExtractorRejection(_)))
+    ));
+}
+
+/// Attempts a WebSocket upgrade, returning the status code the server responded with
+async fn upgrade_status(url: String, headers: &[(&'static str, &str)]) -> StatusCode {
+    let mut upgrade_request = url.into_client_request().unwrap();
+    for (name, value) in headers {
+        upgrade_request
+            .headers_mut()
+            .insert(*name, value.parse().unwrap());
+    }
+
+    match connect_async(upgrade_request).await {
+        Ok((_, response)) => response.status(),
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response.status(),
+        Err(e) => panic!("Unexpected error upgrading: {e}"),
+    }
+}
+
+#[tokio::test]
+async fn should_refuse_upgrades_by_path_origin_and_callback() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_websocket_path("/rpc")
+            .with_allowed_origins(["https://example.com"])
+            .on_upgrade(|upgrade| {
+                Box::pin(async move {
+                    match upgrade.headers().get("authorization") {
+                        Some(_) => Ok(()),
+                        None => Err(StatusCode::UNAUTHORIZED),
+                    }
+                })
+            })
+            .build()
+            .serve_listener(listener)
+            .await
+    });
+
+    let authorized = ("authorization", "Bearer abc");
+
+    assert_eq!(
+        upgrade_status(format!("ws://{address}/other"), &[authorized]).await,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        upgrade_status(
+            format!("ws://{address}/rpc"),
+            &[authorized, ("origin", "https://evil.com")]
+        )
+        .await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        upgrade_status(format!("ws://{address}/rpc"), &[]).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        upgrade_status(
+            format!("ws://{address}/rpc"),
+            &[authorized, ("origin", "https://example.com")]
+        )
+        .await,
+        StatusCode::SWITCHING_PROTOCOLS
+    );
+}
+
+#[tokio::test]
+async fn should_handle_requests_after_filtered_upgrade() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_websocket_path("/rpc")
+            .build()
+            .serve_listener(listener)
+            .await
+    });
+
+    let mut client = Client::new_websocket(&format!("ws://{address}/rpc"))
+        .await
+        .unwrap();
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+
+    assert_eq!(response, "hello");
+}
+
+async fn reject_all(
+    _connection: Arc<ConnectionDetails<()>>,
+    request: LiRpcRequest,
+    _state: (),
+    _next: Next<(), ()>,
+) -> LiRpcResponse {
+    LiRpcResponse::from_protocol_error(
+        request.headers.id,
+        LiRpcProtocolError::ExtractorRejection("rejected by middleware".into()),
+    )
+}
+
+#[tokio::test]
+async fn should_run_global_middleware_for_every_handler() {
+    let calls = Arc::new(AtomicU32::new(0));
+    let counted = calls.clone();
+
+    let server = ServerBuilder::new()
+        .layer(
+            move |connection, request: LiRpcRequest, state, next: Next<(), ()>| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                next.run(connection, request, state)
+            },
+        )
+        .with_handlers(handlers!(greet))
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+    for _ in 0..3 {
+        let response = client
+            .call::<(), String>("greet".to_string(), None)
+            .await
+            .unwrap()
+            .resolve()
+            .await
+            .unwrap();
+        assert_eq!(response, "hello");
+    }
+
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn should_short_circuit_only_handlers_in_group() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_handler_group(handlers!(whoami), reject_all)
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+
+    let response = client
+        .call::<(), Vec<u8>>("whoami".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::ExtractorRejection(detail)))
+            if detail == json!("rejected by middleware")
+    ));
+
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_run_later_middleware_outermost() {
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let layer = |name: &'static str| {
+        let order = order.clone();
+        move |connection, request: LiRpcRequest, state, next: Next<(), ()>| {
+            order.lock().unwrap().push(name);
+            next.run(connection, request, state)
+        }
+    };
+
+    let server = ServerBuilder::new()
+        .with_handler_group(handlers!(greet), layer("group"))
+        .layer(layer("inner"))
+        .layer(layer("outer"))
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+    client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+
+    assert_eq!(*order.lock().unwrap(), ["outer", "inner", "group"]);
+}
+
+#[tokio::test]
+async fn should_respond_with_server_error_when_handler_panics() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(panics, greet))
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+    let response = client
+        .call::<(), String>("panics".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::ServerError(_)))
+    ));
+
+    // The connection stays usable
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_respond_with_server_error_when_middleware_panics_before_its_future() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_handler_group(
+            handlers!(whoami),
+            |_connection,
+             _request: LiRpcRequest,
+             _state,
+             _next: Next<(), ()>|
+             -> future::Ready<LiRpcResponse> { panic!("middleware went wrong") },
+        )
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+    let response = client
+        .call::<(), Vec<u8>>("whoami".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::ServerError(_)))
+    ));
+
+    // The connection stays usable
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_respond_with_server_error_when_handler_panics_over_websocket() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(panics))
+        .build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let (mut ws, _) = connect_async(format!("ws://{address}")).await.unwrap();
+    ws.send(Message::text(
+        json!({"headers": {"id": 7, "function": "panics"}, "payload": null}).to_string(),
+    ))
+    .await
+    .unwrap();
+
+    let response = match ws.next().await.unwrap().unwrap() {
+        Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+        message => panic!("unexpected message: {message:?}"),
+    };
+    assert_eq!(response["headers"]["id"], 7);
+    assert_eq!(response["headers"]["protocol_error"], true);
+    assert_eq!(response["payload"]["error"], "server_error");
+}
+
+#[tokio::test]
+async fn should_respond_with_timeout_when_handler_takes_too_long() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(never_returns, slow))
+        .with_request_timeout(Duration::from_millis(20))
+        .with_method_timeout("slow", Duration::from_secs(5))
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+
+    let response = client
+        .call::<(), ()>("never_returns".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+    assert!(matches!(
+        response,
+        Err(ClientError::Protocol(ProtocolError::Timeout(_)))
+    ));
+
+    // The method timeout takes precedence over the default
+    let response = client
+        .call::<(), String>("slow".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "done");
+}
+
+#[tokio::test]
+async fn should_stream_items_to_client_until_handler_returns() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(count_to_three, gives_up_after_two))
+        .build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+
+    let items: Vec<u32> = client
+        .call_stream::<(), u32>("count_to_three".to_string(), None)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(items, [1, 2, 3]);
+
+    // The error of the handler is the last item of the stream
+    let mut stream = client
+        .call_stream::<(), u32>("gives_up_after_two".to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+    assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(ClientError::Domain(error))) if error == "gave up"
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn should_end_stream_when_streaming_handler_times_out() {
+    let (mut framed, _) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(stalled_stream))
+            .with_request_timeout(Duration::from_millis(20)),
+        1024,
+    );
+
+    let request = json!({"headers": {"id": 1, "function": "stalled_stream"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let item: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(item["headers"]["stream"], "item");
+    let timeout: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(timeout["headers"]["stream"], "end");
+    assert_eq!(timeout["payload"]["error"], "timeout");
+
+    // The client ends the stream with the timeout
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(stalled_stream))
+        .with_request_timeout(Duration::from_millis(20))
+        .build();
+    let mut client = server.connect_in_memory().await.unwrap();
+    let mut stream = client
+        .call_stream::<(), u32>("stalled_stream".to_string(), None)
+        .await
+        .unwrap();
+
+    assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(ClientError::Protocol(ProtocolError::Timeout(_))))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn should_respect_timeout_requested_by_client() {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(never_returns))
+            .with_request_timeout(Duration::from_secs(60))
+            .build()
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    let request = json!({
+        "headers": {"id": 1, "function": "never_returns", "timeout_ms": 20},
+        "payload": null
+    });
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let response = tokio::time::timeout(Duration::from_secs(5), framed.next())
+        .await
+        .expect("the requested timeout should apply")
+        .unwrap()
+        .unwrap();
+    let response: Value = serde_json::from_slice(&response).unwrap();
+
+    assert_eq!(response["headers"]["id"], 1);
+    assert_eq!(response["headers"]["protocol_error"], true);
+    assert_eq!(response["payload"]["error"], "timeout");
+}
+
+#[tokio::test]
+async fn should_stop_waiting_for_call_after_client_timeout() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(never_returns, greet))
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+
+    let response = client
+        .call::<(), ()>("never_returns".to_string(), None)
+        .await
+        .unwrap()
+        .resolve_timeout(Duration::from_millis(20))
+        .await;
+    assert!(matches!(response, Err(ClientError::Timeout)));
+
+    let mut client = client.with_default_timeout(Duration::from_millis(20));
+    let response = client
+        .call::<(), ()>("never_returns".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+    assert!(matches!(
+        response,
+        Err(ClientError::Timeout | ClientError::Protocol(ProtocolError::Timeout(_)))
+    ));
+
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_abort_handler_on_cancellation() {
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(cancellable, greet))
+            .build_with_state(events_tx)
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    let request = json!({"headers": {"id": 1, "function": "cancellable"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await, "started");
+
+    for request in [
+        json!({"headers": {"id": 1, "function": "lirpc_cancel"}, "payload": null}),
+        json!({"headers": {"id": 2, "function": "greet"}, "payload": null}),
+    ] {
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(next_event(&mut events).await, "dropped");
+
+    // Neither the cancelled request nor the cancellation itself get a response
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 2}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_handle_cancellations_at_in_flight_limit() {
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(cancellable, greet))
+            .with_max_in_flight_requests(2)
+            .build_with_state(events_tx)
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    for request in [
+        json!({"headers": {"id": 1, "function": "cancellable"}, "payload": null}),
+        json!({"headers": {"id": 2, "function": "cancellable"}, "payload": null}),
+    ] {
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(next_event(&mut events).await, "started");
+    assert_eq!(next_event(&mut events).await, "started");
+
+    // Both the request waiting for room and one of the running ones are cancelled
+    for request in [
+        json!({"headers": {"id": 3, "function": "greet"}, "payload": null}),
+        json!({"headers": {"id": 3, "function": "lirpc_cancel"}, "payload": null}),
+        json!({"headers": {"id": 1, "function": "lirpc_cancel"}, "payload": null}),
+    ] {
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(next_event(&mut events).await, "dropped");
+
+    let request = json!({"headers": {"id": 4, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 4}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_cancel_request_when_call_is_dropped() {
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(cancellable, greet))
+        .build_with_state(events_tx);
+
+    let mut client = server.connect_in_memory().await.unwrap();
+
+    let call = client
+        .call::<(), ()>("cancellable".to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await, "started");
+    drop(call);
+    assert_eq!(next_event(&mut events).await, "dropped");
+
+    let call = client
+        .call::<(), ()>("cancellable".to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await, "started");
+    call.cancel();
+    assert_eq!(next_event(&mut events).await, "dropped");
+
+    let response = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_limit_in_flight_requests_per_connection() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(slow))
+        .with_max_in_flight_requests(1)
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+
+    let start = Instant::now();
+    let first = client
+        .call::<(), String>("slow".to_string(), None)
+        .await
+        .unwrap();
+    let second = client
+        .call::<(), String>("slow".to_string(), None)
+        .await
+        .unwrap();
+
+    let (first, second) = tokio::join!(first.resolve(), second.resolve());
+    assert_eq!(first.unwrap(), "done");
+    assert_eq!(second.unwrap(), "done");
+
+    // The second request is only handled once the first one is done
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn should_respond_with_small_response_queue() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_response_queue_depth(1)
+        .build();
+
+    let mut client = server.connect_in_memory().await.unwrap();
+
+    let mut calls = Vec::new();
+    for _ in 0..10 {
+        calls.push(
+            client
+                .call::<(), String>("greet".to_string(), None)
+                .await
+                .unwrap(),
+        );
+    }
+
+    for call in calls {
+        assert_eq!(call.resolve().await.unwrap(), "hello");
+    }
+}
+
+#[tokio::test]
+async fn should_wait_with_accepting_connections_at_maximum() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_max_connections(1)
+        .build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    let request: bytes::Bytes = Bytes::from(serde_json::to_vec(&request).unwrap());
+
+    let mut first =
+        LengthDelimitedCodec::builder().new_framed(TcpStream::connect(address).await.unwrap());
+    first.send(request.clone()).await.unwrap();
+    assert!(first.next().await.unwrap().is_ok());
+
+    let mut second =
+        LengthDelimitedCodec::builder().new_framed(TcpStream::connect(address).await.unwrap());
+    second.send(request).await.unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), second.next())
+            .await
+            .is_err(),
+        "the second connection should not be served while the first one is open"
+    );
+
+    drop(first);
+
+    let response = tokio::time::timeout(Duration::from_secs(5), second.next())
+        .await
+        .expect("the second connection should be served once the first one closed")
+        .unwrap()
+        .unwrap();
+    let response: Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+/// Sends requests for the given methods over a raw connection,
+/// returning the ids of the responses in the order they arrived
+async fn response_order(server: Server<(), ()>, methods: &[&str]) -> Vec<u64> {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    for (id, method) in methods.iter().enumerate() {
+        let request = json!({"headers": {"id": id + 1, "function": method}, "payload": null});
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+
+    let mut ids = Vec::new();
+    for _ in methods {
+        let response: Value =
+            serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+        ids.push(response["headers"]["id"].as_u64().unwrap());
+    }
+
+    ids
+}
+
+#[tokio::test]
+async fn should_handle_requests_in_order_with_sequential_execution() {
+    let concurrent = ServerBuilder::new()
+        .with_handlers(handlers!(slow, greet))
+        .build();
+    assert_eq!(response_order(concurrent, &["slow", "greet"]).await, [2, 1]);
+
+    let sequential = ServerBuilder::new()
+        .with_handlers(handlers!(slow, greet))
+        .with_sequential_execution()
+        .build();
+    assert_eq!(
+        response_order(sequential, &["slow", "greet", "slow"]).await,
+        [1, 2, 3]
+    );
+}
+
+#[tokio::test]
+async fn should_only_order_requests_of_sequential_handlers() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_sequential_handlers(handlers!(slow, upgrade_info))
+        .build();
+
+    // `upgrade_info` is rejected right away, but has to wait for `slow`,
+    // while `greet` doesn't
+    assert_eq!(
+        response_order(server, &["slow", "upgrade_info", "greet"]).await,
+        [3, 1, 2]
+    );
+}
+
+#[tokio::test]
+async fn should_forget_sequential_handlers_replaced_by_with_handlers() {
+    let server = ServerBuilder::new()
+        .with_sequential_handlers(handlers!(slow, upgrade_info))
+        .with_handlers(handlers!(slow, upgrade_info))
+        .build();
+
+    // `upgrade_info` is rejected right away, as it no longer waits for `slow`
+    assert_eq!(
+        response_order(server, &["slow", "upgrade_info"]).await,
+        [2, 1]
+    );
+}
+
+/// Serves a single raw connection over a duplex stream with a buffer of `buffer_size` bytes,
+/// returning the client end and the reason the server disconnected
+fn serve_with_disconnect_reason(
+    builder: ServerBuilder<(), ()>,
+    buffer_size: usize,
+) -> (
+    tokio_util::codec::Framed<tokio::io::DuplexStream, LengthDelimitedCodec>,
+    oneshot::Receiver<DisconnectReason>,
+) {
+    let (disconnected_tx, disconnected_rx) = oneshot::channel();
+    let disconnected_tx = std::sync::Mutex::new(Some(disconnected_tx));
+
+    let server = builder
+        .on_disconnect(move |_, reason| {
+            if let Some(disconnected_tx) = disconnected_tx.lock().unwrap().take() {
+                disconnected_tx.send(reason).ok();
+            }
+            Box::pin(async {})
+        })
+        .build();
+
+    let (client_stream, server_stream) = tokio::io::duplex(buffer_size);
+    tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    (
+        LengthDelimitedCodec::builder().new_framed(client_stream),
+        disconnected_rx,
+    )
+}
+
+#[tokio::test]
+async fn should_close_idle_connections() {
+    let (mut framed, disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(slow))
+            .with_read_idle_timeout(Duration::from_millis(100)),
+        1024,
+    );
+
+    // Requests that are still being handled keep the connection open
+    let request = json!({"headers": {"id": 1, "function": "slow"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "done"}));
+
+    let closed = tokio::time::timeout(Duration::from_secs(5), framed.next())
+        .await
+        .expect("the idle connection should be closed");
+    assert!(closed.is_none());
+    assert_eq!(disconnected.await.unwrap(), DisconnectReason::Idle);
+}
+
+#[tokio::test]
+async fn should_close_connections_idling_before_being_set_up() {
+    let server = Arc::new(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_read_idle_timeout(Duration::from_millis(100))
+            .build(),
+    );
+
+    // Nothing to classify the connection by, and an unfinished websocket upgrade
+    for sent in [&b""[..], b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n"] {
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let server = server.clone();
+        tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+        client_stream.write_all(sent).await.unwrap();
+        let mut buf = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client_stream.read_to_end(&mut buf))
+            .await
+            .expect("the idle connection should be closed")
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn should_close_connection_sending_too_large_message() {
+    let (mut framed, disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_max_message_size(64),
+        1024,
+    );
+
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": "x".repeat(64)});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        disconnected.await.unwrap(),
+        DisconnectReason::Error(_)
+    ));
+}
+
+#[tokio::test]
+async fn should_close_connection_when_writing_times_out() {
+    // The response doesn't fit in the buffer and is never read
+    let (mut framed, disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_write_timeout(Duration::from_millis(50)),
+        16,
+    );
+
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let reason = tokio::time::timeout(Duration::from_secs(5), disconnected)
+        .await
+        .expect("the connection should be closed")
+        .unwrap();
+    assert!(matches!(reason, DisconnectReason::Error(e) if e.contains("timed out")));
+}
+
+#[tokio::test]
+async fn should_fail_calls_when_client_closes_idle_connection() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(never_returns))
+        .build();
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    let options = ConnectionOptions::default().with_read_idle_timeout(Duration::from_millis(50));
+    let mut client = Client::new_tcp_with_stream_and_options(client_stream, options)
+        .await
+        .unwrap();
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(5),
+        client
+            .call::<(), ()>("never_returns".to_string(), None)
+            .await
+            .unwrap()
+            .resolve(),
+    )
+    .await
+    .expect("the call should fail once the connection is closed");
+    assert!(matches!(response, Err(ClientError::TokioRecv(_))));
+}
+
+#[tokio::test]
+async fn should_fail_calls_when_response_exceeds_client_message_size() {
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(large_response))
+        .build();
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    let options = ConnectionOptions::default().with_max_message_size(256);
+    let mut client = Client::new_tcp_with_stream_and_options(client_stream, options)
+        .await
+        .unwrap();
+
+    let response = client
+        .call::<(), String>("large_response".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await;
+    assert!(matches!(response, Err(ClientError::TokioRecv(_))));
+}
+
+#[tokio::test]
+async fn should_answer_websocket_pings() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new().with_handlers(handlers!(greet)).build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let (mut ws, _) = connect_async(format!("ws://{address}")).await.unwrap();
+    ws.send(Message::Ping("are you there".into()))
+        .await
+        .unwrap();
+    assert_eq!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Pong("are you there".into())
+    );
+
+    // Unsolicited pongs are ignored as well, the connection stays open
+    ws.send(Message::Pong("still here".into())).await.unwrap();
+    ws.send(Message::text(
+        json!({"headers": {"id": 1, "function": "greet"}, "payload": null}).to_string(),
+    ))
+    .await
+    .unwrap();
+
+    let response = match ws.next().await.unwrap().unwrap() {
+        Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+        message => panic!("unexpected message: {message:?}"),
+    };
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_answer_pings_over_tcp() {
+    let (mut framed, _) = serve_with_disconnect_reason(ServerBuilder::new(), 1024);
+
+    let request = json!({"headers": {"id": 3, "function": "lirpc_ping"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 3}, "payload": null}));
+}
+
+#[tokio::test]
+async fn should_not_close_connection_at_in_flight_limit_as_unresponsive() {
+    let (mut framed, mut disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(never_returns))
+            .with_max_in_flight_requests(1)
+            .with_heartbeat(Duration::from_millis(50), Duration::from_millis(50)),
+        1024,
+    );
+
+    // One request runs and one waits for room, so the server stops reading
+    for id in [1, 2] {
+        let request = json!({"headers": {"id": id, "function": "never_returns"}, "payload": null});
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(matches!(
+        disconnected.try_recv(),
+        Err(oneshot::error::TryRecvError::Empty)
+    ));
+}
+
+#[tokio::test]
+async fn should_ping_websocket_clients_and_close_unresponsive_ones() {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let (disconnected_tx, disconnected_rx) = oneshot::channel();
+    let disconnected_tx = std::sync::Mutex::new(Some(disconnected_tx));
+    let server = ServerBuilder::new()
+        .with_heartbeat(Duration::from_millis(50), Duration::from_millis(50))
+        .on_disconnect(move |_, reason| {
+            if let Some(disconnected_tx) = disconnected_tx.lock().unwrap().take() {
+                disconnected_tx.send(reason).ok();
+            }
+            Box::pin(async {})
+        })
+        .build();
+    tokio::spawn(async move { server.serve_connection(server_stream).await });
+
+    let (mut ws, _) = client_async("ws://localhost/", client_stream)
+        .await
+        .unwrap();
+    assert!(matches!(
+        ws.next().await.unwrap().unwrap(),
+        Message::Ping(_)
+    ));
+
+    // Not reading any further, so the ping is never answered
+    let reason = tokio::time::timeout(Duration::from_secs(5), disconnected_rx)
+        .await
+        .expect("the unresponsive connection should be closed")
+        .unwrap();
+    assert_eq!(reason, DisconnectReason::Unresponsive);
+}
+
+#[tokio::test]
+async fn should_close_tcp_connections_without_heartbeat() {
+    let (mut framed, disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new().with_heartbeat(Duration::from_millis(50), Duration::from_millis(50)),
+        1024,
+    );
+
+    // A single heartbeat, after which the client goes silent
+    let request = json!({"headers": {"id": 1, "function": "lirpc_ping"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    framed.next().await.unwrap().unwrap();
+
+    let reason = tokio::time::timeout(Duration::from_secs(5), disconnected)
+        .await
+        .expect("the unresponsive connection should be closed")
+        .unwrap();
+    assert_eq!(reason, DisconnectReason::Unresponsive);
+}
+
+#[tokio::test]
+async fn should_keep_connections_with_heartbeat_alive() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_heartbeat(Duration::from_millis(50), Duration::from_millis(50))
+        .build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let options = ConnectionOptions::default()
+        .with_heartbeat(Duration::from_millis(20), Duration::from_secs(1));
+    let mut tcp_client = Client::new_tcp_plain_with_options(address, options.clone())
+        .await
+        .unwrap();
+    let mut ws_client = Client::new_websocket_with_options(&format!("ws://{address}"), options)
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let response = tcp_client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+
+    let response = ws_client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_fail_calls_when_server_does_not_answer_heartbeat() {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    // Reads everything, but never answers
+    let mut server = LengthDelimitedCodec::builder().new_framed(server_stream);
+
+    let options = ConnectionOptions::default()
+        .with_heartbeat(Duration::from_millis(50), Duration::from_millis(50));
+    let mut client = Client::new_tcp_with_stream_and_options(client_stream, options)
+        .await
+        .unwrap();
+    let call = client
+        .call::<(), ()>("never_returns".to_string(), None)
+        .await
+        .unwrap();
+
+    let request: Value = serde_json::from_slice(&server.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(request["headers"]["function"], "never_returns");
+    let ping: Value = serde_json::from_slice(&server.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(ping["headers"]["function"], "lirpc_ping");
+
+    let response = tokio::time::timeout(Duration::from_secs(5), call.resolve())
+        .await
+        .expect("the call should fail once the connection is closed");
+    assert!(matches!(response, Err(ClientError::TokioRecv(_))));
+}
+
+#[tokio::test]
+async fn should_fall_back_to_json_for_unsupported_codec() {
+    let (mut framed, _) =
+        serve_with_disconnect_reason(ServerBuilder::new().with_handlers(handlers!(greet)), 1024);
+
+    framed
+        .send(Bytes::from(
+            serde_json::to_vec(&json!({"codec": "yaml"})).unwrap(),
+        ))
+        .await
+        .unwrap();
+    let answer: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(answer, json!({"codec": "json"}));
+
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_accept_binary_websocket_messages() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new().with_handlers(handlers!(greet)).build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let (mut ws, _) = connect_async(format!("ws://{address}")).await.unwrap();
+    let request = json!({"headers": {"id": 1, "function": "greet"}, "payload": null});
+    ws.send(Message::binary(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+
+    let response = match ws.next().await.unwrap().unwrap() {
+        Message::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+        message => panic!("unexpected message: {message:?}"),
+    };
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+async fn call_greet_with_codec(codec: lirpc_rs_client::Codec) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new().with_handlers(handlers!(greet)).build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let options = ConnectionOptions::default().with_codec(codec);
+    let mut tcp_client = Client::new_tcp_plain_with_options(address, options.clone())
+        .await
+        .unwrap();
+    let mut ws_client = Client::new_websocket_with_options(&format!("ws://{address}"), options)
+        .await
+        .unwrap();
+
+    for response in [
+        tcp_client
+            .call::<(), String>("greet".to_string(), None)
+            .await,
+        ws_client
+            .call::<(), String>("greet".to_string(), None)
+            .await,
+    ] {
+        let response = response.unwrap().resolve().await.unwrap();
+        assert_eq!(response, "hello");
+    }
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn should_negotiate_message_pack() {
+    call_greet_with_codec(lirpc_rs_client::Codec::MessagePack).await;
+}
+
+#[cfg(feature = "cbor")]
+#[tokio::test]
+async fn should_negotiate_cbor() {
+    call_greet_with_codec(lirpc_rs_client::Codec::Cbor).await;
+}
+
+fn hello_request(api: Value) -> Bytes {
+    let request = json!({
+        "headers": {"id": 1, "function": "lirpc_hello"},
+        "payload": {"protocol_version": 1, "api": api},
+    });
+
+    Bytes::from(serde_json::to_vec(&request).unwrap())
+}
+
+#[tokio::test]
+async fn should_answer_hello_with_api_identity() {
+    let (mut framed, _) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_api_identity("greeter", "1.2.3"),
+        1024,
+    );
+
+    framed.send(hello_request(Value::Null)).await.unwrap();
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["headers"], json!({"id": 1}));
+    assert_eq!(response["payload"]["protocol_version"], 1);
+    assert_eq!(response["payload"]["api"]["name"], "greeter");
+    assert_eq!(response["payload"]["api"]["version"], "1.2.3");
+    assert_eq!(
+        response["payload"]["api"]["fingerprint"]
+            .as_str()
+            .unwrap()
+            .len(),
+        16
+    );
+}
+
+#[tokio::test]
+async fn should_only_warn_about_incompatible_clients_by_default() {
+    let (mut framed, _) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_api_identity("greeter", "1.2.3"),
+        1024,
+    );
+
+    let other_api =
+        json!({"name": "greeter", "version": "1.0.0", "fingerprint": "0000000000000000"});
+    framed.send(hello_request(other_api)).await.unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["headers"], json!({"id": 1}));
+
+    let request = json!({"headers": {"id": 2, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 2}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_refuse_incompatible_clients() {
+    let (mut framed, disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_api_identity("greeter", "1.2.3")
+            .refuse_incompatible_clients(),
+        1024,
+    );
+
+    let other_api = json!({"name": "auth", "version": "1.2.3", "fingerprint": "0000000000000000"});
+    framed.send(hello_request(other_api)).await.unwrap();
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["headers"]["protocol_error"], true);
+    assert_eq!(response["payload"]["error"], "incompatible");
+
+    assert!(framed.next().await.is_none());
+    assert_eq!(disconnected.await.unwrap(), DisconnectReason::Incompatible);
+}
+
+#[tokio::test]
+async fn should_expose_server_hello_to_client() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_api_identity("greeter", "1.2.3")
+        .refuse_incompatible_clients()
+        .build();
+    let identity = server
+        .compile_api_spec("greeter".to_string(), "1.2.3".to_string())
+        .unwrap()
+        .identity();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    assert!(client.server_hello().is_none());
+
+    let own_identity = lirpc_rs_client::ApiIdentity::new(
+        identity.name.clone(),
+        identity.version.clone(),
+        identity.fingerprint.clone(),
+    );
+    let server_hello = client.hello(Some(own_identity.clone())).await.unwrap();
+    assert_eq!(server_hello.protocol_version, 1);
+    assert_eq!(server_hello.api, Some(own_identity));
+    assert_eq!(client.server_hello(), Some(&server_hello));
+
+    let mut other_client = Client::new_tcp_plain(address).await.unwrap();
+    let result = other_client
+        .hello(Some(lirpc_rs_client::ApiIdentity::new(
+            "greeter",
+            "1.0.0",
+            "0000000000000000",
+        )))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ClientError::Protocol(ProtocolError::Incompatible(_)))
+    ));
+}
+
+#[tokio::test]
+async fn should_serve_api_spec_with_reflection() {
+    let (mut framed, _) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet, slow))
+            .with_api_identity("greeter", "1.2.3")
+            .with_reflection(),
+        4096,
+    );
+
+    let request = json!({"headers": {"id": 1, "function": "lirpc_methods"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(
+        response,
+        json!({"headers": {"id": 1}, "payload": ["greet", "slow"]})
+    );
+
+    let expected_spec = ServerBuilder::<(), ()>::new()
+        .with_handlers(handlers!(greet, slow))
+        .build()
+        .compile_api_spec("greeter".to_string(), "1.2.3".to_string())
+        .unwrap();
+
+    let request = json!({"headers": {"id": 2, "function": "lirpc_spec"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["headers"], json!({"id": 2}));
+    assert_eq!(
+        response["payload"],
+        serde_json::to_value(&expected_spec).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn should_not_serve_api_spec_without_reflection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new().with_handlers(handlers!(greet)).build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    assert!(matches!(
+        client.spec().await,
+        Err(ClientError::Protocol(ProtocolError::UnknownMethod(_)))
+    ));
+    assert!(matches!(
+        client.methods().await,
+        Err(ClientError::Protocol(ProtocolError::UnknownMethod(_)))
+    ));
+}
+
+#[tokio::test]
+async fn should_discover_methods_through_client() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_reflection()
+        .build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    assert_eq!(client.methods().await.unwrap(), vec!["greet".to_string()]);
+
+    let spec = client.spec().await.unwrap();
+    assert_eq!(spec["name"], "");
+    assert!(spec["methods"]["greet"].is_object());
+}
+
+#[tokio::test]
+async fn should_run_notifications_without_responding() {
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_notification_handlers(handlers!(record))
+            .build_with_state(events_tx)
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    for request in [
+        json!({"headers": {"id": 1, "function": "record", "notification": true}, "payload": null}),
+        json!({"headers": {"id": 1, "function": "unknown", "notification": true}, "payload": null}),
+        json!({"headers": {"id": 2, "function": "greet"}, "payload": null}),
+    ] {
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(next_event(&mut events).await, "recorded");
+
+    // Only the regular request is responded to
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 2}, "payload": "hello"}));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), framed.next())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn should_not_respond_to_reserved_or_malformed_notifications() {
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .build()
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    for request in [
+        json!({"headers": {"id": 1, "function": "lirpc_ping", "notification": true}, "payload": null}),
+        json!({"headers": {"id": 2, "function": "lirpc_hello", "notification": true}, "payload": {"lirpc": "0.0.0"}}),
+        json!({"headers": {"id": 3, "notification": true}, "payload": null}),
+        json!({"headers": {"id": 4, "function": "greet"}, "payload": null}),
+    ] {
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 4}, "payload": "hello"}));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), framed.next())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn should_cancel_request_sharing_its_id_with_notification() {
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+    tokio::spawn(async move {
+        ServerBuilder::new()
+            .with_handlers(handlers!(cancellable))
+            .with_notification_handlers(handlers!(record))
+            .build_with_state(events_tx)
+            .serve_connection(server_stream)
+            .await
+    });
+
+    let mut framed = LengthDelimitedCodec::builder().new_framed(client_stream);
+    for request in [
+        json!({"headers": {"id": 1, "function": "cancellable"}, "payload": null}),
+        json!({"headers": {"id": 1, "function": "record", "notification": true}, "payload": null}),
+    ] {
+        framed
+            .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+            .await
+            .unwrap();
+    }
+    let mut started = [next_event(&mut events).await, next_event(&mut events).await];
+    started.sort();
+    assert_eq!(started, ["recorded", "started"]);
+
+    let request = json!({"headers": {"id": 1, "function": "lirpc_cancel"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await, "dropped");
+}
+
+#[tokio::test]
+async fn should_keep_connection_open_while_notification_runs() {
+    let (mut framed, _disconnected) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(greet))
+            .with_notification_handlers(handlers!(slow))
+            .with_read_idle_timeout(Duration::from_millis(100)),
+        1024,
+    );
+
+    let request =
+        json!({"headers": {"id": 1, "function": "slow", "notification": true}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    let request = json!({"headers": {"id": 2, "function": "greet"}, "payload": null});
+    framed
+        .send(Bytes::from(serde_json::to_vec(&request).unwrap()))
+        .await
+        .unwrap();
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 2}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_mark_notification_methods_in_api_spec() {
+    let spec = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_notification_handlers(handlers!(record))
+        .build_with_state(mpsc::unbounded_channel().0)
+        .compile_api_spec("notifying".to_string(), "0.1.0".to_string())
+        .unwrap();
+
+    assert!(spec.methods["record"].notification);
+    assert!(!spec.methods["greet"].notification);
+}
+
+#[tokio::test]
+async fn should_forget_notification_handlers_replaced_by_with_handlers() {
+    let spec = ServerBuilder::new()
+        .with_notification_handlers(handlers!(record))
+        .with_handlers(handlers!(record))
+        .build_with_state(mpsc::unbounded_channel().0)
+        .compile_api_spec("notifying".to_string(), "0.1.0".to_string())
+        .unwrap();
+
+    assert!(!spec.methods["record"].notification);
+}
+
+#[tokio::test]
+async fn should_send_notifications_from_client() {
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(greet))
+        .with_notification_handlers(handlers!(record))
+        .build_with_state(events_tx);
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+    client
+        .notify::<()>("record".to_string(), None)
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await, "recorded");
+
+    let response: String = client
+        .call::<(), String>("greet".to_string(), None)
+        .await
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+    assert_eq!(response, "hello");
+}
+
+#[tokio::test]
+async fn should_handle_batched_requests_concurrently() {
+    let (mut framed, _) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(slow, greet))
+            .with_max_in_flight_requests(2),
+        4096,
+    );
+
+    let batch = json!([
+        {"headers": {"id": 1, "function": "slow"}, "payload": null},
+        {"headers": {"id": 2, "function": "greet"}, "payload": null},
+        {"headers": {"id": 3}, "payload": null},
+    ]);
+    framed
+        .send(Bytes::from(serde_json::to_vec(&batch).unwrap()))
+        .await
+        .unwrap();
+
+    let mut responses = Vec::new();
+    for _ in 0..3 {
+        let response: Value =
+            serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+        responses.push(response);
+    }
+
+    // Responded to separately, the slow request doesn't hold up the others
+    assert_eq!(
+        responses[2],
+        json!({"headers": {"id": 1}, "payload": "done"})
+    );
+    assert!(responses.contains(&json!({"headers": {"id": 2}, "payload": "hello"})));
+    let malformed = responses
+        .iter()
+        .find(|response| response["headers"]["id"] == 3)
+        .unwrap();
+    assert_eq!(malformed["payload"]["error"], "malformed_envelope");
+}
+
+#[tokio::test]
+async fn should_limit_in_flight_requests_within_batch() {
+    let (mut framed, _) = serve_with_disconnect_reason(
+        ServerBuilder::new()
+            .with_handlers(handlers!(slow, greet))
+            .with_max_in_flight_requests(1),
+        4096,
+    );
+
+    let batch = json!([
+        {"headers": {"id": 1, "function": "slow"}, "payload": null},
+        {"headers": {"id": 2, "function": "greet"}, "payload": null},
+    ]);
+    framed
+        .send(Bytes::from(serde_json::to_vec(&batch).unwrap()))
+        .await
+        .unwrap();
+
+    // Each request of the batch waits for room of its own
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "done"}));
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 2}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_not_respond_to_empty_batch() {
+    let (mut framed, _) =
+        serve_with_disconnect_reason(ServerBuilder::new().with_handlers(handlers!(greet)), 1024);
+
+    framed.send(Bytes::from_static(b"[]")).await.unwrap();
+    framed
+        .send(Bytes::from_static(
+            br#"{"headers":{"id":1,"function":"greet"}}"#,
+        ))
+        .await
+        .unwrap();
+
+    // The empty batch has no id to respond to, so the greeting comes first
+    let response: Value = serde_json::from_slice(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(response, json!({"headers": {"id": 1}, "payload": "hello"}));
+}
+
+#[tokio::test]
+async fn should_resolve_batched_calls_independently() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = ServerBuilder::new()
+        .with_handlers(handlers!(slow, greet))
+        .build();
+    tokio::spawn(async move { server.serve_listener(listener).await });
+
+    let mut client = Client::new_tcp_plain(address).await.unwrap();
+
+    let mut batch = client.batch();
+    let slow_call = batch.call::<(), String>("slow".to_string(), None).unwrap();
+    let greet_call = batch.call::<(), String>("greet".to_string(), None).unwrap();
+    assert_eq!(batch.len(), 2);
+    batch.send().await.unwrap();
+
+    let started = Instant::now();
+    assert_eq!(greet_call.resolve().await.unwrap(), "hello");
+    assert!(started.elapsed() < Duration::from_millis(200));
+    assert_eq!(slow_call.resolve().await.unwrap(), "done");
+
+    // Calls of a batch that is never sent don't resolve
+    let mut batch = client.batch();
+    let unsent = batch.call::<(), String>("greet".to_string(), None).unwrap();
+    drop(batch);
+    assert!(unsent.resolve().await.is_err());
+}
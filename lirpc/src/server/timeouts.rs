@@ -0,0 +1,69 @@
+use std::{fmt::Display, future, time::Duration};
+
+use tokio::time::Instant;
+
+/// Tracks when a connection counts as idle, see `ServerBuilder::with_read_idle_timeout`
+pub(crate) struct IdleTimer {
+    timeout: Option<Duration>,
+    deadline: Instant,
+}
+
+impl IdleTimer {
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        let mut timer = Self {
+            timeout,
+            deadline: Instant::now(),
+        };
+        timer.reset();
+
+        timer
+    }
+
+    /// Restarts the timer, e.g. because something was received
+    pub(crate) fn reset(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.deadline = Instant::now() + timeout;
+        }
+    }
+
+    /// Completes once the connection has been idle for the timeout, never without a timeout
+    pub(crate) async fn elapsed(&self) {
+        match self.timeout {
+            Some(_) => tokio::time::sleep_until(self.deadline).await,
+            None => future::pending().await,
+        }
+    }
+
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+/// Waits for `write` to complete, giving up once `timeout` (if set) passes
+pub(crate) async fn write_within<E>(
+    timeout: Option<Duration>,
+    write: impl Future<Output = Result<(), E>>,
+) -> Result<(), String>
+where
+    E: Display,
+{
+    let Some(timeout) = timeout else {
+        return write.await.map_err(|e| e.to_string());
+    };
+
+    match tokio::time::timeout(timeout, write).await {
+        Ok(written) => written.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("writing timed out after {timeout:?}")),
+    }
+}
+
+/// Waits for `read` to complete, giving up with `None` once `timeout` (if set) passes
+pub(crate) async fn read_within<T>(
+    timeout: Option<Duration>,
+    read: impl Future<Output = T>,
+) -> Option<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read).await.ok(),
+        None => Some(read.await),
+    }
+}
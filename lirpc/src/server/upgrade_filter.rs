@@ -0,0 +1,124 @@
+use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use tracing::debug;
+
+use crate::extractors::UpgradeHeaders;
+
+/// Upgrade requests with more headers than this are refused
+const MAX_UPGRADE_REQUEST_HEADERS: usize = 64;
+/// Upgrade requests larger than this (in bytes) are refused
+const MAX_UPGRADE_REQUEST_LENGTH: usize = 16 * 1024;
+
+pub(crate) type OnUpgrade =
+    Box<dyn for<'a> Fn(&'a UpgradeHeaders) -> BoxFuture<'a, Result<(), StatusCode>> + Send + Sync>;
+
+/// Decides which WebSocket upgrade requests are accepted
+#[derive(Default)]
+pub(crate) struct UpgradeFilter {
+    pub(crate) allowed_origins: Option<Vec<String>>,
+    pub(crate) path: Option<String>,
+    pub(crate) on_upgrade: Option<OnUpgrade>,
+}
+
+impl UpgradeFilter {
+    /// Whether every upgrade request is accepted
+    pub(crate) fn accepts_all(&self) -> bool {
+        self.allowed_origins.is_none() && self.path.is_none() && self.on_upgrade.is_none()
+    }
+
+    pub(crate) async fn check(&self, upgrade: &UpgradeHeaders) -> Result<(), StatusCode> {
+        if let Some(path) = &self.path
+            && upgrade.uri().path() != path
+        {
+            return Err(StatusCode::NOT_FOUND);
+        }
+
+        // Only browsers send the `Origin` header, other clients could spoof it anyway
+        if let Some(allowed_origins) = &self.allowed_origins
+            && let Some(origin) = upgrade.headers().get("origin")
+            && !allowed_origins
+                .iter()
+                .any(|allowed| allowed.as_bytes().eq_ignore_ascii_case(origin.as_bytes()))
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        match &self.on_upgrade {
+            Some(on_upgrade) => on_upgrade(upgrade).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the HTTP upgrade request from the stream, continuing from the already read `buffer`.
+/// Returns all bytes read, as they still have to be handled by the WebSocket handshake.
+pub(crate) async fn read_upgrade_request<I>(
+    mut buffer: BytesMut,
+    stream: &mut I,
+) -> Result<(Bytes, UpgradeHeaders), StatusCode>
+where
+    I: AsyncRead + Unpin,
+{
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_UPGRADE_REQUEST_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+
+        match request.parse(&buffer) {
+            Ok(httparse::Status::Complete(_)) => {
+                let upgrade = to_upgrade_headers(&request)?;
+                return Ok((buffer.freeze(), upgrade));
+            }
+            Ok(httparse::Status::Partial) => {}
+            Err(httparse::Error::TooManyHeaders) => {
+                return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+            }
+            Err(_) => return Err(StatusCode::BAD_REQUEST),
+        }
+
+        if buffer.len() >= MAX_UPGRADE_REQUEST_LENGTH {
+            return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        }
+
+        match stream.read_buf(&mut buffer).await {
+            Ok(0) | Err(_) => return Err(StatusCode::BAD_REQUEST),
+            Ok(_) => {}
+        }
+    }
+}
+
+fn to_upgrade_headers(request: &httparse::Request) -> Result<UpgradeHeaders, StatusCode> {
+    let uri = request
+        .path
+        .and_then(|path| path.parse::<Uri>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let mut headers = HeaderMap::new();
+    for header in request.headers.iter() {
+        let name =
+            HeaderName::from_bytes(header.name.as_bytes()).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let value = HeaderValue::from_bytes(header.value).map_err(|_| StatusCode::BAD_REQUEST)?;
+        headers.append(name, value);
+    }
+
+    Ok(UpgradeHeaders::new(uri, headers))
+}
+
+/// Responds to the upgrade request with `status` and closes the connection
+pub(crate) async fn refuse_upgrade<I>(stream: &mut I, status: StatusCode)
+where
+    I: AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    );
+
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        debug!("Error refusing websocket upgrade: {e}");
+        return;
+    }
+    stream.shutdown().await.ok();
+}
@@ -1,11 +1,12 @@
-use std::{pin::Pin, sync::Arc};
+use std::{pin::Pin, sync::Arc, time::Duration};
 
 use crate::{
     api_spec::LiRpcMethodSpec,
     connection_details::ConnectionDetails,
+    error::LiRpcProtocolError,
     handler::Handler,
+    into_lirpc_response::IntoLiRpcResponse,
     lirpc_message::{LiRpcRequest, LiRpcResponse},
-    translatable::Translatable,
 };
 
 pub(crate) trait Service<S, C>
@@ -32,7 +33,7 @@ where
     C: Clone + Send + Sync + 'static,
     F: 'static,
     T: 'static,
-    R: Translatable + 'static,
+    R: IntoLiRpcResponse + 'static,
 {
     fn call(
         &self,
@@ -47,3 +48,78 @@ where
         self.0.get_spec()
     }
 }
+
+/// Gives up on a service that takes longer than the configured timeout,
+/// or the timeout of the request itself if that is shorter
+pub(crate) struct TimeoutService<S, C> {
+    service: Box<dyn Service<S, C>>,
+    timeout: Option<Duration>,
+    /// Whether the service streams its output, the timeout then ends the stream
+    streams: bool,
+}
+
+impl<S, C> TimeoutService<S, C>
+where
+    S: Send + Sync + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    pub(crate) fn wrap(
+        service: Box<dyn Service<S, C>>,
+        timeout: Option<Duration>,
+    ) -> Box<dyn Service<S, C>> {
+        let streams = service.get_spec().stream.is_some();
+
+        Box::new(Self {
+            service,
+            timeout,
+            streams,
+        })
+    }
+}
+
+impl<S, C> Service<S, C> for TimeoutService<S, C>
+where
+    S: Send + Sync + 'static,
+    C: Clone + Send + Sync + 'static,
+{
+    fn call(
+        &self,
+        connection: Arc<ConnectionDetails<C>>,
+        message: LiRpcRequest,
+        state: S,
+    ) -> Pin<Box<dyn Future<Output = LiRpcResponse> + Send>> {
+        let requested = message.headers.timeout_ms.map(Duration::from_millis);
+        let timeout = match (self.timeout, requested) {
+            (Some(configured), Some(requested)) => Some(configured.min(requested)),
+            (configured, requested) => configured.or(requested),
+        };
+
+        let message_id = message.headers.id;
+        let ends_stream = self.streams;
+        let response = self.service.call(connection, message, state);
+
+        let Some(timeout) = timeout else {
+            return response;
+        };
+
+        Box::pin(async move {
+            // Dropping the response future cancels the handler
+            tokio::time::timeout(timeout, response)
+                .await
+                .unwrap_or_else(|_| {
+                    LiRpcResponse::from_protocol_error(
+                        message_id,
+                        LiRpcProtocolError::Timeout(format!(
+                            "the method did not respond within {} ms",
+                            timeout.as_millis()
+                        )),
+                    )
+                    .ending_stream(ends_stream)
+                })
+        })
+    }
+
+    fn get_spec(&self) -> LiRpcMethodSpec {
+        self.service.get_spec()
+    }
+}
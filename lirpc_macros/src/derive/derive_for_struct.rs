@@ -78,5 +78,15 @@ pub fn derive_translatable_for_struct(item: ItemStruct) -> TokenStream {
                 lirpc::translatable::Type::TypeRef(#name_string.to_string())
             }
         }
+
+        impl #gs_with_clauses lirpc::into_lirpc_response::IntoLiRpcResponse for #name #gs {
+            fn into_lirpc_response(self, message_id: u32) -> lirpc::lirpc_message::LiRpcResponse {
+                lirpc::into_lirpc_response::build_lirpc_response::<_, ()>(message_id, Ok(self))
+            }
+
+            fn ok_type() -> lirpc::translatable::Type {
+                <Self as lirpc::translatable::Translatable>::get_type()
+            }
+        }
     })
 }
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+use crate::{
+    Call, CancelOnDrop, Client, PendingResponse,
+    error::Error,
+    lirpc_message::{LiRpcRequest, LiRpcRequestHeaders},
+    transport::Transport,
+};
+
+/// Collects calls to send them to the server in a single frame, see `Client::batch`.
+///
+/// The server handles the calls of a batch concurrently and responds to each of
+/// them separately, so every `Call` resolves independently of the others.
+pub struct Batch<'c, T: Transport<F>, F> {
+    client: &'c mut Client<T, F>,
+    requests: Vec<LiRpcRequest<Value>>,
+    pending: Vec<(u32, PendingResponse)>,
+}
+
+impl<'c, T, F> Batch<'c, T, F>
+where
+    T: Transport<F>,
+{
+    pub(crate) fn new(client: &'c mut Client<T, F>) -> Self {
+        Self {
+            client,
+            requests: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Adds a call to the batch. The returned `Call` resolves once the batch is sent
+    /// and the server responded, or fails when the batch is dropped without sending it.
+    pub fn call<M, R>(&mut self, function: String, payload: Option<M>) -> Result<Call<R>, Error>
+    where
+        M: Serialize,
+        R: for<'de> Deserialize<'de>,
+    {
+        let timeout = self.client.default_timeout;
+        let id = self.push(function, payload, timeout, false)?;
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.push((id, PendingResponse::Call(tx)));
+
+        Ok(Call::new(
+            rx,
+            CancelOnDrop::new(id, self.client.cancellations.clone()),
+            timeout,
+        ))
+    }
+
+    /// Adds a notification to the batch, see `Client::notify`
+    pub fn notify<M>(&mut self, function: String, payload: Option<M>) -> Result<(), Error>
+    where
+        M: Serialize,
+    {
+        self.push(function, payload, None, true)?;
+
+        Ok(())
+    }
+
+    /// The number of calls and notifications in the batch
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Sends all calls and notifications of the batch in a single frame
+    pub async fn send(self) -> Result<(), Error> {
+        if self.requests.is_empty() {
+            return Ok(());
+        }
+
+        let mut rp_lock = self.client.response_pending.lock().await;
+        rp_lock.extend(self.pending);
+        drop(rp_lock);
+
+        self.client.transport.lock().await.send(self.requests).await
+    }
+
+    fn push<M>(
+        &mut self,
+        function: String,
+        payload: Option<M>,
+        timeout: Option<Duration>,
+        notification: bool,
+    ) -> Result<u32, Error>
+    where
+        M: Serialize,
+    {
+        let id = self.client.get_new_request_id();
+        self.requests.push(LiRpcRequest {
+            headers: LiRpcRequestHeaders {
+                id,
+                function,
+                timeout_ms: timeout.map(|t| t.as_millis().try_into().unwrap_or(u64::MAX)),
+                notification,
+            },
+            payload: payload.map(serde_json::to_value).transpose()?,
+        });
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::error::Error;
+
+/// How messages are encoded on the wire. Mirrors `lirpc::codec::Codec`,
+/// the server has to be built with the same features to support a codec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Codec {
+    /// The name the codec is negotiated with
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => "msgpack",
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => "cbor",
+        }
+    }
+
+    /// Whether the encoded messages are binary, instead of text
+    pub fn is_binary(self) -> bool {
+        !matches!(self, Codec::Json)
+    }
+
+    /// The WebSocket subprotocol the codec is negotiated with
+    pub(crate) fn subprotocol(self) -> String {
+        format!("lirpc.{}", self.name())
+    }
+
+    pub(crate) fn encode<M: Serialize>(self, message: &M) -> Result<Vec<u8>, Error> {
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(message)?),
+            // Named, as fields that are skipped when serializing would shift the fields of a tuple
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                rmp_serde::to_vec_named(message).map_err(|e| Error::Codec(e.to_string()))
+            }
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::into_writer(message, &mut encoded)
+                    .map_err(|e| Error::Codec(e.to_string()))?;
+
+                Ok(encoded)
+            }
+        }
+    }
+
+    pub(crate) fn decode<M: DeserializeOwned>(self, raw: &[u8]) -> Result<M, Error> {
+        match self {
+            Codec::Json => Ok(serde_json::from_slice(raw)?),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                rmp_serde::from_slice(raw).map_err(|e| Error::Codec(e.to_string()))
+            }
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::from_reader(raw).map_err(|e| Error::Codec(e.to_string())),
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use serde::Deserialize;
+use serde_json::Value;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("IoError: {0}")]
@@ -10,8 +13,48 @@ pub enum Error {
         "The address '{0}' is not a valid address and couldn't be converted to a servername for use with PKI"
     )]
     InvalidAddress(String),
+    #[error("TlsError: {0}")]
+    Tls(#[from] rustls::Error),
     #[error("WebsocketError: {0}")]
     Websocket(#[from] tokio_tungstenite::tungstenite::Error),
-    #[error("ServerError: {error}: {detail}")]
-    Server { error: String, detail: String },
+    #[error("ProtocolError: {0}")]
+    Protocol(#[from] ProtocolError),
+    #[error("The method responded with an error: {0}")]
+    Domain(Value),
+    #[error("The call did not resolve in time")]
+    Timeout,
+    #[error("CodecError: {0}")]
+    Codec(String),
+    #[error("The server doesn't support the {0} codec")]
+    UnsupportedCodec(&'static str),
+}
+
+/// The error of a call to a method that can respond with errors of type `E`
+#[derive(Debug, thiserror::Error)]
+pub enum CallError<E> {
+    #[error("The method responded with an error: {0:?}")]
+    Domain(E),
+    #[error(transparent)]
+    Client(#[from] Error),
+}
+
+/// Errors on the protocol level the server responds with.
+/// Mirrors `lirpc::error::LiRpcProtocolError`.
+#[derive(Debug, Clone, PartialEq, Deserialize, thiserror::Error)]
+#[serde(rename_all = "snake_case", tag = "error", content = "detail")]
+pub enum ProtocolError {
+    #[error("unknown method: {0}")]
+    UnknownMethod(String),
+    #[error("malformed envelope: {0}")]
+    MalformedEnvelope(String),
+    #[error("malformed payload: {0}")]
+    MalformedPayload(String),
+    #[error("extractor rejection: {0}")]
+    ExtractorRejection(Value),
+    #[error("server error: {0}")]
+    ServerError(String),
+    #[error("timeout: {0}")]
+    Timeout(String),
+    #[error("incompatible: {0}")]
+    Incompatible(String),
 }
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// Reserved method exchanging the protocol version and the identity of the api
+/// with the server, see `Client::hello`
+pub(crate) const HELLO_METHOD: &str = "lirpc_hello";
+/// Version of the protocol spoken by this client.
+/// Mirrors `lirpc::lirpc_message::PROTOCOL_VERSION`.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+/// Which api spec a server serves, or a client was generated from.
+/// Mirrors `lirpc::api_spec::ApiIdentity`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiIdentity {
+    pub name: String,
+    pub version: String,
+    /// Hash of the methods and types of the spec
+    pub fingerprint: String,
+}
+
+impl ApiIdentity {
+    pub fn new(
+        name: impl Into<String>,
+        version: impl Into<String>,
+        fingerprint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+            fingerprint: fingerprint.into(),
+        }
+    }
+}
+
+/// What the server answered to the `lirpc_hello` handshake
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ServerHello {
+    pub protocol_version: u32,
+    /// Not set when the server wasn't given an api identity
+    #[serde(default)]
+    pub api: Option<ApiIdentity>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ClientHello {
+    pub(crate) protocol_version: u32,
+    pub(crate) api: Option<ApiIdentity>,
+}
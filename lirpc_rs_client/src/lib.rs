@@ -1,10 +1,28 @@
+mod batch;
+mod codec;
 pub mod error;
+mod hello;
 mod lirpc_message;
 mod serializers;
 pub mod transport;
 
-use std::{collections::BTreeMap, marker::PhantomData, sync::Arc};
+pub use batch::Batch;
+pub use codec::Codec;
+pub use hello::{ApiIdentity, ServerHello};
+pub use rustls;
+pub use transport::ConnectionOptions;
 
+use std::{
+    collections::BTreeMap,
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::Stream;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::{
@@ -18,27 +36,63 @@ use tokio::{
 };
 use tokio_rustls::client::TlsStream;
 use tokio_util::bytes::Bytes;
-use tracing::{error, warn};
+use tracing::{debug, error, warn};
 
 use crate::{
-    error::Error,
-    lirpc_message::{LiRpcRequest, LiRpcRequestHeaders, LiRpcResponse, LiRpcServerError},
+    error::{CallError, Error, ProtocolError},
+    hello::{ClientHello, HELLO_METHOD, PROTOCOL_VERSION},
+    lirpc_message::{HEARTBEAT_ID, LiRpcRequest, LiRpcRequestHeaders, LiRpcResponse},
     serializers::Serializer,
     transport::{Transport, tcp::Tcp, websocket::Websocket},
 };
 
+#[cfg(unix)]
+use crate::transport::unix::Unix;
+
+type ResponsePending = Arc<Mutex<BTreeMap<u32, PendingResponse>>>;
+
+/// Reserved method cancelling the request with the id in its headers
+const CANCEL_METHOD: &str = "lirpc_cancel";
+/// Reserved method responding with the api spec of servers with reflection
+const SPEC_METHOD: &str = "lirpc_spec";
+/// Reserved method responding with the method names of servers with reflection
+const METHODS_METHOD: &str = "lirpc_methods";
+
+/// Where the response(s) to a request should be forwarded to
+enum PendingResponse {
+    Call(oneshot::Sender<LiRpcResponse<Value>>),
+    Stream(mpsc::UnboundedSender<LiRpcResponse<Value>>),
+}
+
 pub struct Client<T: Transport<F>, F> {
     id_counter: u32,
-    transport: T,
-    response_pending: Arc<Mutex<BTreeMap<u32, oneshot::Sender<LiRpcResponse<Value>>>>>,
+    transport: Arc<Mutex<T>>,
+    response_pending: ResponsePending,
+    /// The ids of requests to cancel, sent out by a background task
+    cancellations: mpsc::UnboundedSender<u32>,
+    /// Applies to every `Call` that is resolved without an explicit timeout
+    default_timeout: Option<Duration>,
+    /// Set once the server answered `hello`
+    server_hello: Option<ServerHello>,
     f: PhantomData<F>,
 }
 
 impl Client<Tcp<TcpStream>, Bytes> {
     /// No TLS: unencrypted
     pub async fn new_tcp_plain(address: impl ToSocketAddrs) -> Result<Self, Error> {
+        Self::new_tcp_plain_with_options(address, ConnectionOptions::default()).await
+    }
+
+    /// Like `new_tcp_plain`, with custom connection options
+    pub async fn new_tcp_plain_with_options(
+        address: impl ToSocketAddrs,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
         let (tx, rx) = mpsc::channel(10);
-        Self::new_tcp_with_transport(rx, Tcp::connect(address, tx).await?).await
+        Ok(Self::with_transport(
+            rx,
+            Tcp::connect(address, &options, tx).await?,
+        ))
     }
 }
 
@@ -46,7 +100,48 @@ impl Client<Tcp<TlsStream<TcpStream>>, Bytes> {
     /// TLS: encrypted
     pub async fn new_tcp_tls(address: String) -> Result<Self, Error> {
         let (tx, rx) = mpsc::channel(10);
-        Self::new_tcp_with_transport(rx, Tcp::connect_tls(address, tx).await?).await
+        Ok(Self::with_transport(
+            rx,
+            Tcp::connect_tls(address, &ConnectionOptions::default(), tx).await?,
+        ))
+    }
+
+    /// TLS: encrypted, with a custom rustls config.
+    /// E.g. for trusting a private CA or presenting a client certificate.
+    pub async fn new_tcp_tls_with_config(
+        address: String,
+        config: impl Into<Arc<rustls::ClientConfig>>,
+    ) -> Result<Self, Error> {
+        Self::new_tcp_tls_with_options(address, config, ConnectionOptions::default()).await
+    }
+
+    /// Like `new_tcp_tls_with_config`, with custom connection options
+    pub async fn new_tcp_tls_with_options(
+        address: String,
+        config: impl Into<Arc<rustls::ClientConfig>>,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel(10);
+        let transport = Tcp::connect_tls_with_config(address, config.into(), &options, tx).await?;
+        Ok(Self::with_transport(rx, transport))
+    }
+
+    /// TLS: encrypted, authenticating to the server with the given client certificate (mutual TLS).
+    /// The server itself is verified against the webpki root certificates,
+    /// use `new_tcp_tls_with_config` to trust other certificates.
+    pub async fn new_tcp_tls_with_client_certificate(
+        address: String,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, Error> {
+        let root_store =
+            rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_client_auth_cert(cert_chain, key)?;
+
+        Self::new_tcp_tls_with_config(address, config).await
     }
 }
 
@@ -54,37 +149,113 @@ impl<S> Client<Tcp<S>, Bytes>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    async fn new_tcp_with_transport(rx: Receiver<Bytes>, transport: Tcp<S>) -> Result<Self, Error> {
-        let response_pending = Arc::new(Mutex::new(BTreeMap::new()));
+    /// Uses the length-delimited framing of the TCP transport
+    /// over an already established stream of any kind
+    pub async fn new_tcp_with_stream(stream: S) -> Result<Self, Error> {
+        Self::new_tcp_with_stream_and_options(stream, ConnectionOptions::default()).await
+    }
 
-        let rp = response_pending.clone();
-        tokio::spawn(async move { Self::message_router(rx, rp).await });
+    /// Like `new_tcp_with_stream`, with custom connection options
+    pub async fn new_tcp_with_stream_and_options(
+        stream: S,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel(10);
+        Ok(Self::with_transport(
+            rx,
+            Tcp::setup_with_stream(stream, &options, tx).await?,
+        ))
+    }
+}
 
-        Ok(Self {
-            id_counter: 0,
-            transport,
-            response_pending,
-            f: PhantomData,
-        })
+#[cfg(unix)]
+impl Client<Unix, Bytes> {
+    /// Connects over the Unix domain socket at `path`
+    pub async fn new_unix(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        Self::new_unix_with_options(path, ConnectionOptions::default()).await
+    }
+
+    /// Like `new_unix`, with custom connection options
+    pub async fn new_unix_with_options(
+        path: impl AsRef<std::path::Path>,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel(10);
+        Ok(Self::with_transport(
+            rx,
+            Unix::connect(path, &options, tx).await?,
+        ))
     }
 }
 
-impl Client<Websocket, String> {
+impl Client<Websocket, Bytes> {
     pub async fn new_websocket(url: &str) -> Result<Self, Error> {
+        Self::new_websocket_with_options(url, ConnectionOptions::default()).await
+    }
+
+    /// Like `new_websocket`, with custom connection options
+    pub async fn new_websocket_with_options(
+        url: &str,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
         let (tx, rx) = mpsc::channel(10);
-        let transport = Websocket::connect(url, tx).await?;
+        let transport = Websocket::connect(url, &options, tx).await?;
+
+        Ok(Self::with_transport(rx, transport))
+    }
 
+    /// Like `new_websocket`, with a custom rustls config for `wss` urls.
+    /// E.g. for trusting a private CA or presenting a client certificate.
+    pub async fn new_websocket_with_tls_config(
+        url: &str,
+        config: impl Into<Arc<rustls::ClientConfig>>,
+    ) -> Result<Self, Error> {
+        Self::new_websocket_with_tls_options(url, config, ConnectionOptions::default()).await
+    }
+
+    /// Like `new_websocket_with_tls_config`, with custom connection options
+    pub async fn new_websocket_with_tls_options(
+        url: &str,
+        config: impl Into<Arc<rustls::ClientConfig>>,
+        options: ConnectionOptions,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel(10);
+        let transport =
+            Websocket::connect_with_tls_config(url, config.into(), &options, tx).await?;
+
+        Ok(Self::with_transport(rx, transport))
+    }
+}
+
+impl<T, F> Client<T, F>
+where
+    T: Transport<F> + Send + 'static,
+    F: Send + 'static,
+{
+    /// Sets up the client on top of a connected transport,
+    /// which forwards the messages it receives to `rx`
+    fn with_transport(rx: Receiver<F>, transport: T) -> Self {
         let response_pending = Arc::new(Mutex::new(BTreeMap::new()));
+        let codec = transport.codec();
+        let transport = Arc::new(Mutex::new(transport));
+        let (cancellations, cancellations_rx) = mpsc::unbounded_channel();
+
+        let rp = response_pending.clone();
+        tokio::spawn(async move { Self::message_router(rx, codec, rp).await });
 
         let rp = response_pending.clone();
-        tokio::spawn(async move { Self::message_router(rx, rp).await });
+        let t = transport.clone();
+        tokio::spawn(async move { Self::send_cancellations(cancellations_rx, t, rp).await });
 
-        Ok(Self {
+        Self {
             id_counter: 0,
             transport,
             response_pending,
+            cancellations,
+            default_timeout: None,
+            server_hello: None,
             f: PhantomData,
-        })
+        }
     }
 }
 
@@ -92,23 +263,98 @@ impl<T, F> Client<T, F>
 where
     T: Transport<F>,
 {
+    /// Makes every call give up on its response after `timeout`, unless it is resolved
+    /// with `Call::resolve_timeout`. The timeout is also sent along with the request,
+    /// so the server can stop working on it. Streamed calls are not affected.
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Tells the server which protocol version and api the client speaks, e.g. the
+    /// `api_identity()` of a generated client, and learns the same about the server.
+    /// Servers refusing incompatible clients respond with `ProtocolError::Incompatible`
+    /// and close the connection.
+    pub async fn hello(&mut self, api: Option<ApiIdentity>) -> Result<ServerHello, Error> {
+        let hello = ClientHello {
+            protocol_version: PROTOCOL_VERSION,
+            api,
+        };
+        let server_hello: ServerHello = self
+            .call(HELLO_METHOD.to_string(), Some(hello))
+            .await?
+            .resolve()
+            .await?;
+        self.server_hello = Some(server_hello.clone());
+
+        Ok(server_hello)
+    }
+
+    /// What the server answered to `hello`, if it was called
+    pub fn server_hello(&self) -> Option<&ServerHello> {
+        self.server_hello.as_ref()
+    }
+
+    /// The api spec of the server, in the format of the `api_spec.json` files codegen
+    /// works with. Only served by servers with reflection, others respond with
+    /// `ProtocolError::UnknownMethod`.
+    pub async fn spec(&mut self) -> Result<Value, Error> {
+        self.call::<(), Value>(SPEC_METHOD.to_string(), None)
+            .await?
+            .resolve()
+            .await
+    }
+
+    /// The names of the methods of the server, see `spec`
+    pub async fn methods(&mut self) -> Result<Vec<String>, Error> {
+        self.call::<(), Vec<String>>(METHODS_METHOD.to_string(), None)
+            .await?
+            .resolve()
+            .await
+    }
+
     fn get_new_request_id(&mut self) -> u32 {
         self.id_counter = self.id_counter.wrapping_add(1);
+        if self.id_counter == HEARTBEAT_ID {
+            self.id_counter = self.id_counter.wrapping_add(1);
+        }
         self.id_counter
     }
 
     async fn message_router(
         mut rx: mpsc::Receiver<F>,
-        response_pending: Arc<Mutex<BTreeMap<u32, oneshot::Sender<LiRpcResponse<Value>>>>>,
+        codec: Codec,
+        response_pending: ResponsePending,
     ) {
         while let Some(msg) = rx.recv().await {
-            let deserialized_msg: LiRpcResponse<Value> = match T::Serializer::deserialize(&msg) {
-                Ok(m) => m,
-                _ => continue,
-            };
+            let deserialized_msg: LiRpcResponse<Value> =
+                match T::Serializer::deserialize(codec, &msg) {
+                    Ok(m) => m,
+                    _ => continue,
+                };
+
+            let id = deserialized_msg.headers.id;
+            if id == HEARTBEAT_ID {
+                // The answer to a heartbeat of the transport, no call is waiting for it
+                continue;
+            }
 
             let mut rp_lock = response_pending.lock().await;
-            let sender = rp_lock.remove(&deserialized_msg.headers.id);
+            let sender = match rp_lock.get(&id) {
+                // Stream items keep the listener registered until the stream ends
+                Some(PendingResponse::Stream(sender))
+                    if deserialized_msg.headers.is_stream_item() =>
+                {
+                    if sender.send(deserialized_msg).is_err() {
+                        // The `CallStream` was dropped, no need to keep forwarding
+                        rp_lock.remove(&id);
+                    }
+                    continue;
+                }
+                Some(_) => rp_lock.remove(&id),
+                None => None,
+            };
             drop(rp_lock);
 
             let sender = match sender {
@@ -119,13 +365,80 @@ where
                 }
             };
 
-            match sender.send(deserialized_msg) {
-                Ok(_) => {}
-                Err(e) => error!("error during message forwarding: {e:?}"),
+            match sender {
+                PendingResponse::Call(sender) => {
+                    if let Err(e) = sender.send(deserialized_msg) {
+                        error!("error during message forwarding: {e:?}");
+                    }
+                }
+                PendingResponse::Stream(sender) => {
+                    if let Err(e) = sender.send(deserialized_msg) {
+                        error!("error during message forwarding: {e:?}");
+                    }
+                }
+            };
+        }
+
+        // The connection is closed, no responses will come in anymore
+        response_pending.lock().await.clear();
+    }
+
+    /// Sends a cancellation for every request whose `Call` was dropped before
+    /// it resolved, until the client and all its calls are dropped
+    async fn send_cancellations(
+        mut cancellations: mpsc::UnboundedReceiver<u32>,
+        transport: Arc<Mutex<T>>,
+        response_pending: ResponsePending,
+    ) {
+        while let Some(id) = cancellations.recv().await {
+            response_pending.lock().await.remove(&id);
+
+            let message = LiRpcRequest::<()> {
+                headers: LiRpcRequestHeaders {
+                    id,
+                    function: CANCEL_METHOD.to_string(),
+                    timeout_ms: None,
+                    notification: false,
+                },
+                payload: None,
             };
+
+            if let Err(e) = transport.lock().await.send(message).await {
+                debug!("Error cancelling request ({id}): {e}");
+            }
         }
     }
 
+    async fn send_request<M>(
+        &mut self,
+        function: String,
+        payload: Option<M>,
+        pending: PendingResponse,
+        timeout: Option<Duration>,
+    ) -> Result<u32, Error>
+    where
+        M: Serialize,
+    {
+        let message = LiRpcRequest {
+            headers: LiRpcRequestHeaders {
+                id: self.get_new_request_id(),
+                function,
+                timeout_ms: timeout.map(|t| t.as_millis().try_into().unwrap_or(u64::MAX)),
+                notification: false,
+            },
+            payload,
+        };
+        let id = message.headers.id;
+
+        let mut rp_lock = self.response_pending.lock().await;
+        rp_lock.insert(id, pending);
+        drop(rp_lock);
+
+        self.transport.lock().await.send(message).await?;
+
+        Ok(id)
+    }
+
     pub async fn call<M, R>(
         &mut self,
         function: String,
@@ -134,32 +447,139 @@ where
     where
         M: Serialize,
         R: for<'de> Deserialize<'de>,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        let id = self
+            .send_request(
+                function,
+                payload,
+                PendingResponse::Call(tx),
+                self.default_timeout,
+            )
+            .await?;
+
+        Ok(Call::new(
+            rx,
+            CancelOnDrop::new(id, self.cancellations.clone()),
+            self.default_timeout,
+        ))
+    }
+
+    /// Sends a notification: the server runs the method, but sends no response back.
+    /// Resolves once the notification is sent, there is no way to know whether
+    /// the method succeeded.
+    pub async fn notify<M>(&mut self, function: String, payload: Option<M>) -> Result<(), Error>
+    where
+        M: Serialize,
     {
         let message = LiRpcRequest {
             headers: LiRpcRequestHeaders {
                 id: self.get_new_request_id(),
                 function,
+                timeout_ms: None,
+                notification: true,
             },
             payload,
         };
 
-        let (tx, rx) = oneshot::channel();
+        self.transport.lock().await.send(message).await
+    }
 
-        let mut rp_lock = self.response_pending.lock().await;
-        rp_lock.insert(message.headers.id, tx);
-        drop(rp_lock);
+    /// Starts a batch of calls, sent to the server in a single frame
+    ///
+    /// # Example
+    /// ```rs
+    /// let mut batch = client.batch();
+    /// let greeting = batch.call::<(), String>("greet".to_string(), None)?;
+    /// let count = batch.call::<(), u32>("count".to_string(), None)?;
+    /// batch.send().await?;
+    ///
+    /// let (greeting, count) = (greeting.resolve().await?, count.resolve().await?);
+    /// ```
+    pub fn batch(&mut self) -> Batch<'_, T, F> {
+        Batch::new(self)
+    }
+
+    /// Calls a method that streams its output (a method using the
+    /// `OutputStream` extractor on the server). The returned `CallStream`
+    /// yields every streamed item until the method returns.
+    pub async fn call_stream<M, R>(
+        &mut self,
+        function: String,
+        payload: Option<M>,
+    ) -> Result<CallStream<R>, Error>
+    where
+        M: Serialize,
+        R: for<'de> Deserialize<'de>,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let id = self
+            .send_request(function, payload, PendingResponse::Stream(tx), None)
+            .await?;
 
-        self.transport.send(message).await?;
+        Ok(CallStream::new(
+            rx,
+            CancelOnDrop::new(id, self.cancellations.clone()),
+        ))
+    }
+}
+
+/// Turns a response with the `res: err` header into an error,
+/// leaving errors returned by the method itself as raw json.
+fn response_error(response: LiRpcResponse<Value>) -> Error {
+    if !response.headers.protocol_error {
+        return Error::Domain(response.payload);
+    }
 
-        Ok(Call::new(rx))
+    match response.deserialize_payload::<ProtocolError>() {
+        Ok(deserialized_response) => Error::Protocol(deserialized_response.payload),
+        Err(e) => Error::Serde(e),
+    }
+}
+
+/// Cancels a request on the server when dropped, unless it was disarmed
+/// because the response came in
+struct CancelOnDrop {
+    id: u32,
+    cancellations: Option<mpsc::UnboundedSender<u32>>,
+}
+
+impl CancelOnDrop {
+    fn new(id: u32, cancellations: mpsc::UnboundedSender<u32>) -> Self {
+        Self {
+            id,
+            cancellations: Some(cancellations),
+        }
+    }
+
+    fn disarm(&mut self) {
+        self.cancellations = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellations) = self.cancellations.take() {
+            // Fails when the client is gone, in which case there is no one to tell
+            cancellations.send(self.id).ok();
+        }
     }
 }
 
+/// A request waiting for its response.
+///
+/// Dropping a `Call` before it resolved cancels the request: the server
+/// stops the handler and its response is ignored.
 pub struct Call<R>
 where
     R: for<'de> Deserialize<'de>,
 {
     receiver: oneshot::Receiver<LiRpcResponse<Value>>,
+    cancel: CancelOnDrop,
+    /// The default timeout of the client
+    timeout: Option<Duration>,
     _response_type: PhantomData<R>,
 }
 
@@ -167,28 +587,147 @@ impl<R> Call<R>
 where
     R: for<'de> Deserialize<'de>,
 {
-    fn new(receiver: oneshot::Receiver<LiRpcResponse<Value>>) -> Self {
+    fn new(
+        receiver: oneshot::Receiver<LiRpcResponse<Value>>,
+        cancel: CancelOnDrop,
+        timeout: Option<Duration>,
+    ) -> Self {
         Self {
             receiver,
+            cancel,
+            timeout,
             _response_type: PhantomData,
         }
     }
 
+    /// Waits for the response, for at most the default timeout of the client (if set)
     pub async fn resolve(self) -> Result<R, Error> {
-        let response = self.receiver.await?;
+        match self.timeout {
+            Some(timeout) => self.resolve_timeout(timeout).await,
+            None => {
+                let mut cancel = self.cancel;
+                let response = self.receiver.await?;
+                cancel.disarm();
 
-        if !response.headers.res.is_ok() {
-            let deserialized_response: LiRpcResponse<LiRpcServerError> =
-                response.deserialize_payload::<LiRpcServerError>()?;
+                Self::into_result(response)
+            }
+        }
+    }
+
+    /// Waits for the response for at most `timeout`, after which the
+    /// request is cancelled and `Error::Timeout` is returned.
+    pub async fn resolve_timeout(self, timeout: Duration) -> Result<R, Error> {
+        let mut cancel = self.cancel;
+        let response = tokio::time::timeout(timeout, self.receiver)
+            .await
+            .map_err(|_| Error::Timeout)??;
+        cancel.disarm();
+
+        Self::into_result(response)
+    }
 
-            Err(Error::Server {
-                error: deserialized_response.payload.error,
-                detail: deserialized_response.payload.detail,
-            })
+    /// Cancels the request, the same as dropping the `Call`
+    pub fn cancel(self) {}
+
+    fn into_result(response: LiRpcResponse<Value>) -> Result<R, Error> {
+        if !response.headers.res.is_ok() {
+            Err(response_error(response))
         } else {
             let deserialized_response: LiRpcResponse<R> = response.deserialize_payload::<R>()?;
 
             Ok(deserialized_response.payload)
         }
     }
+
+    /// Resolves a call to a method that can respond with errors of type `E`.
+    pub async fn resolve_with_error<E>(self) -> Result<R, CallError<E>>
+    where
+        E: for<'de> Deserialize<'de>,
+    {
+        match self.resolve().await {
+            Ok(r) => Ok(r),
+            Err(Error::Domain(raw_error)) => Err(CallError::Domain(
+                serde_json::from_value(raw_error).map_err(Error::from)?,
+            )),
+            Err(e) => Err(CallError::Client(e)),
+        }
+    }
+}
+
+/// The streamed counterpart of `Call`, yielding the items a method
+/// sends through its `OutputStream`.
+///
+/// The stream ends once the method on the server returns. If the method
+/// (or one of its extractors) fails, the error is yielded as the last item.
+/// The value a method returns when it succeeds is not yielded, only the items
+/// sent through its `OutputStream` are.
+/// Dropping the stream before it ended cancels the method, like dropping a `Call`.
+pub struct CallStream<R>
+where
+    R: for<'de> Deserialize<'de>,
+{
+    receiver: mpsc::UnboundedReceiver<LiRpcResponse<Value>>,
+    cancel: CancelOnDrop,
+    finished: bool,
+    _item_type: PhantomData<fn() -> R>,
+}
+
+impl<R> CallStream<R>
+where
+    R: for<'de> Deserialize<'de>,
+{
+    fn new(receiver: mpsc::UnboundedReceiver<LiRpcResponse<Value>>, cancel: CancelOnDrop) -> Self {
+        Self {
+            receiver,
+            cancel,
+            finished: false,
+            _item_type: PhantomData,
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+        self.cancel.disarm();
+    }
+}
+
+impl<R> Stream for CallStream<R>
+where
+    R: for<'de> Deserialize<'de>,
+{
+    type Item = Result<R, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        let response = match self.receiver.poll_recv(cx) {
+            Poll::Ready(Some(response)) => response,
+            Poll::Ready(None) => {
+                self.finish();
+                return Poll::Ready(None);
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if !response.headers.is_stream_item() {
+            self.finish();
+
+            if response.headers.res.is_ok() {
+                return Poll::Ready(None);
+            }
+        }
+
+        if !response.headers.res.is_ok() {
+            return Poll::Ready(Some(Err(response_error(response))));
+        }
+
+        Poll::Ready(Some(
+            response
+                .deserialize_payload::<R>()
+                .map(|r| r.payload)
+                .map_err(Error::from),
+        ))
+    }
 }
@@ -1,16 +1,40 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+/// Reserved method the server answers right away, used as heartbeat
+pub(crate) const PING_METHOD: &str = "lirpc_ping";
+/// The request id of heartbeats, never used for calls
+pub(crate) const HEARTBEAT_ID: u32 = 0;
+
 #[derive(Debug, Serialize)]
 pub(crate) struct LiRpcRequest<P: Serialize> {
     pub headers: LiRpcRequestHeaders,
     pub payload: Option<P>,
 }
 
+impl LiRpcRequest<()> {
+    pub(crate) fn heartbeat() -> Self {
+        Self {
+            headers: LiRpcRequestHeaders {
+                id: HEARTBEAT_ID,
+                function: PING_METHOD.to_string(),
+                timeout_ms: None,
+                notification: false,
+            },
+            payload: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub(crate) struct LiRpcRequestHeaders {
     pub id: u32,
     pub function: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Set for notifications, which the server sends no response to
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub notification: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,6 +64,24 @@ pub(crate) struct LiRpcResponseHeaders {
         default = "LiRpcResponseResultHeader::ok"
     )]
     pub res: LiRpcResponseResultHeader,
+    #[serde(default)]
+    pub protocol_error: bool,
+    #[serde(default)]
+    pub stream: Option<LiRpcStreamHeader>,
+}
+
+impl LiRpcResponseHeaders {
+    /// Whether more responses will follow for the same request id
+    pub(crate) fn is_stream_item(&self) -> bool {
+        matches!(self.stream, Some(LiRpcStreamHeader::Item))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LiRpcStreamHeader {
+    Item,
+    End,
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,9 +100,3 @@ impl LiRpcResponseResultHeader {
         matches!(self, LiRpcResponseResultHeader::Ok)
     }
 }
-
-#[derive(Deserialize)]
-pub(crate) struct LiRpcServerError {
-    pub error: String,
-    pub detail: String,
-}
@@ -1,18 +1,18 @@
 use tokio_util::bytes::Bytes;
 
-use crate::serializers::Serializer;
+use crate::{codec::Codec, error::Error, serializers::Serializer};
 
 pub struct BytesSerializer;
 
 impl Serializer<Bytes> for BytesSerializer {
-    fn serialize<M: serde::Serialize>(message: M) -> Result<Bytes, serde_json::Error> {
-        Ok(Bytes::from(serde_json::to_vec(&message)?))
+    fn serialize<M: serde::Serialize>(codec: Codec, message: M) -> Result<Bytes, Error> {
+        Ok(Bytes::from(codec.encode(&message)?))
     }
 
-    fn deserialize<M>(raw: &Bytes) -> Result<M, serde_json::Error>
+    fn deserialize<M>(codec: Codec, raw: &Bytes) -> Result<M, Error>
     where
         M: for<'de> serde::Deserialize<'de>,
     {
-        serde_json::from_slice(raw)
+        codec.decode(raw)
     }
 }
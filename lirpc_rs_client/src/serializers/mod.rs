@@ -1,11 +1,12 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{codec::Codec, error::Error};
+
 pub mod bytes_serializer;
-pub mod string_serializer;
 
 pub trait Serializer<F> {
-    fn serialize<M: Serialize>(message: M) -> Result<F, serde_json::Error>;
-    fn deserialize<M>(raw: &F) -> Result<M, serde_json::Error>
+    fn serialize<M: Serialize>(codec: Codec, message: M) -> Result<F, Error>;
+    fn deserialize<M>(codec: Codec, raw: &F) -> Result<M, Error>
     where
         M: for<'de> Deserialize<'de>;
 }
@@ -1,12 +1,185 @@
 pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
 pub mod websocket;
 
+use std::{future, io, time::Duration};
+
 use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::{codec::Codec, error::Error, serializers::Serializer};
+
+/// Default maximum size (in bytes) of a single message, guarding against a
+/// malformed/oversized length prefix causing an unbounded allocation.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Settings of the connection to the server, shared by all transports
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    pub(crate) max_message_size: usize,
+    pub(crate) read_idle_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    /// The interval and timeout of the heartbeat
+    pub(crate) heartbeat: Option<(Duration, Duration)>,
+    pub(crate) codec: Codec,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            read_idle_timeout: None,
+            write_timeout: None,
+            heartbeat: None,
+            codec: Codec::Json,
+        }
+    }
+}
+
+impl ConnectionOptions {
+    /// The maximum size (in bytes) of a single message the server can send,
+    /// the connection is closed when a larger one comes in. Defaults to 8 MiB.
+    pub fn with_max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
 
-use crate::{error::Error, serializers::Serializer};
+        self
+    }
+
+    /// Closes the connection when nothing was received from the server for `timeout`.
+    /// Calls that are still waiting for their response fail. By default the
+    /// connection can stay idle indefinitely.
+    pub fn with_read_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.read_idle_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Fails sending a request when writing it takes longer than `timeout`.
+    /// By default writes can take indefinitely.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Detects a server that is gone without closing the connection. Once nothing was
+    /// received for `interval`, the server is pinged (with a WebSocket ping, or by calling
+    /// the reserved `lirpc_ping` method over length-delimited connections). The connection
+    /// is closed when nothing comes back within `timeout`, failing the calls still waiting
+    /// for their response.
+    pub fn with_heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.heartbeat = Some((interval, timeout));
+
+        self
+    }
+
+    /// Encodes messages with `codec` instead of JSON. It is negotiated with the server
+    /// when connecting, which fails when the server doesn't support the codec.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+
+        self
+    }
+}
+
+/// What the transport has to do about a connection nothing was received on for a while
+pub(crate) enum Silence {
+    /// Ping the server, as part of the heartbeat
+    Ping,
+    /// Close the connection, nothing was received for the read idle timeout
+    Idle,
+    /// Close the connection, the server didn't answer the heartbeat
+    Unresponsive,
+}
+
+/// Keeps track of when the server last sent something, to close the
+/// connection when it is idle or stopped answering the heartbeat
+pub(crate) struct Liveness {
+    read_idle_timeout: Option<Duration>,
+    heartbeat: Option<(Duration, Duration)>,
+    last_received: Instant,
+    pinged_at: Option<Instant>,
+}
+
+impl Liveness {
+    pub(crate) fn new(options: &ConnectionOptions) -> Self {
+        Self {
+            read_idle_timeout: options.read_idle_timeout,
+            heartbeat: options.heartbeat,
+            last_received: Instant::now(),
+            pinged_at: None,
+        }
+    }
+
+    /// Something was received from the server
+    pub(crate) fn received(&mut self) {
+        self.last_received = Instant::now();
+        self.pinged_at = None;
+    }
+
+    /// Completes once the connection was silent for too long, never when
+    /// there is neither a read idle timeout nor a heartbeat
+    pub(crate) async fn silence(&mut self) -> Silence {
+        let idle_deadline = self
+            .read_idle_timeout
+            .map(|timeout| self.last_received + timeout);
+        let heartbeat_deadline = self
+            .heartbeat
+            .map(|(interval, timeout)| match self.pinged_at {
+                Some(pinged_at) => pinged_at + timeout,
+                None => self.last_received + interval,
+            });
+
+        let deadline = match (idle_deadline, heartbeat_deadline) {
+            (Some(idle), Some(heartbeat)) => idle.min(heartbeat),
+            (Some(deadline), None) | (None, Some(deadline)) => deadline,
+            (None, None) => return future::pending().await,
+        };
+        tokio::time::sleep_until(deadline).await;
+
+        if idle_deadline.is_some_and(|idle| idle <= deadline) {
+            Silence::Idle
+        } else if self.pinged_at.is_some() {
+            Silence::Unresponsive
+        } else {
+            self.pinged_at = Some(Instant::now());
+            Silence::Ping
+        }
+    }
+}
+
+/// Waits for `write` to complete, failing when it takes longer than `timeout` (if set)
+pub(crate) async fn write_within<E>(
+    timeout: Option<Duration>,
+    write: impl Future<Output = Result<(), E>>,
+) -> Result<(), Error>
+where
+    Error: From<E>,
+{
+    let Some(timeout) = timeout else {
+        return Ok(write.await?);
+    };
+
+    match tokio::time::timeout(timeout, write).await {
+        Ok(written) => Ok(written?),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("writing to the server timed out after {timeout:?}"),
+        )
+        .into()),
+    }
+}
 
 pub trait Transport<F> {
     type Serializer: Serializer<F>;
 
-    fn send(&mut self, message: impl Serialize) -> impl Future<Output = Result<(), Error>>;
+    /// Sends a single message. The returned future must be `Send`, as the client
+    /// also sends messages from a background task (e.g. to cancel requests).
+    fn send(&mut self, message: impl Serialize) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// The codec the messages forwarded to the client are encoded with
+    fn codec(&self) -> Codec {
+        Codec::Json
+    }
 }
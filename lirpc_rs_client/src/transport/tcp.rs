@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{io, sync::Arc, time::Duration};
 
 use futures::{
     SinkExt, StreamExt,
@@ -6,47 +6,55 @@ use futures::{
 };
 use rustls::pki_types::ServerName;
 use serde::Serialize;
+use serde_json::{Value, json};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::{TcpStream, ToSocketAddrs},
-    sync::mpsc,
+    sync::{Mutex, mpsc},
 };
 use tokio_rustls::{TlsConnector, client::TlsStream};
 use tokio_util::{
     bytes::Bytes,
     codec::{Framed, LengthDelimitedCodec},
 };
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::{
-    error::Error, serializers::Serializer, serializers::bytes_serializer::BytesSerializer,
-    transport::Transport,
+    codec::Codec,
+    error::Error,
+    lirpc_message::LiRpcRequest,
+    serializers::Serializer,
+    serializers::bytes_serializer::BytesSerializer,
+    transport::{ConnectionOptions, Liveness, Silence, Transport, write_within},
 };
 
-/// Maximum size (in bytes) of a single length-prefixed TCP frame, guarding
-/// against a malformed/oversized length prefix causing an unbounded allocation.
-const MAX_TCP_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+type FrameSender<S> = Arc<Mutex<SplitSink<Framed<S, LengthDelimitedCodec>, Bytes>>>;
 
 pub struct Tcp<S>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    sender: SplitSink<Framed<S, LengthDelimitedCodec>, Bytes>,
+    /// Shared with the task receiving messages, to close the connection when it is idle
+    sender: FrameSender<S>,
+    write_timeout: Option<Duration>,
+    codec: Codec,
 }
 
 impl Tcp<TcpStream> {
     pub async fn connect(
         address: impl ToSocketAddrs,
+        options: &ConnectionOptions,
         forward_to: mpsc::Sender<Bytes>,
     ) -> Result<Self, Error> {
         let stream = TcpStream::connect(address).await?;
-        Self::setup_with_stream(stream, forward_to).await
+        Self::setup_with_stream(stream, options, forward_to).await
     }
 }
 
 impl Tcp<TlsStream<TcpStream>> {
     pub async fn connect_tls(
         address: String,
+        options: &ConnectionOptions,
         forward_to: mpsc::Sender<Bytes>,
     ) -> Result<Self, Error> {
         let root_store =
@@ -56,45 +64,137 @@ impl Tcp<TlsStream<TcpStream>> {
             .with_root_certificates(root_store)
             .with_no_client_auth();
 
-        let connector = TlsConnector::from(Arc::new(config));
+        Self::connect_tls_with_config(address, Arc::new(config), options, forward_to).await
+    }
+
+    /// Connects over TLS with a custom rustls config, e.g. one trusting a private CA
+    /// or presenting a client certificate.
+    pub async fn connect_tls_with_config(
+        address: String,
+        config: Arc<rustls::ClientConfig>,
+        options: &ConnectionOptions,
+        forward_to: mpsc::Sender<Bytes>,
+    ) -> Result<Self, Error> {
+        let connector = TlsConnector::from(config);
 
         let tcp_stream = TcpStream::connect(&address).await?;
-        let domain = ServerName::try_from(address.to_string())
+        let domain = ServerName::try_from(host(&address).to_string())
             .map_err(|_| Error::InvalidAddress(address))?
             .to_owned();
         let tls_stream = connector.connect(domain, tcp_stream).await?;
 
-        Self::setup_with_stream(tls_stream, forward_to).await
+        Self::setup_with_stream(tls_stream, options, forward_to).await
     }
 }
 
+/// The host part of an address, without the port
+fn host(address: &str) -> &str {
+    let host = match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => address,
+    };
+
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
 impl<S> Tcp<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    async fn setup_with_stream(stream: S, forward_to: mpsc::Sender<Bytes>) -> Result<Self, Error> {
-        let framed = LengthDelimitedCodec::builder()
-            .max_frame_length(MAX_TCP_FRAME_LENGTH)
+    pub(crate) async fn setup_with_stream(
+        stream: S,
+        options: &ConnectionOptions,
+        forward_to: mpsc::Sender<Bytes>,
+    ) -> Result<Self, Error> {
+        let mut framed = LengthDelimitedCodec::builder()
+            .max_frame_length(options.max_message_size)
             .new_framed(stream);
 
+        if options.codec != Codec::Json {
+            Self::negotiate_codec(&mut framed, options.codec).await?;
+        }
+
         let (sender, receiver) = framed.split();
+        let sender = Arc::new(Mutex::new(sender));
+
+        let s = sender.clone();
+        let liveness = Liveness::new(options);
+        let write_timeout = options.write_timeout;
+        let codec = options.codec;
+        tokio::spawn(async move {
+            Tcp::forward_messages(receiver, s, liveness, write_timeout, codec, forward_to).await
+        });
+
+        Ok(Self {
+            sender,
+            write_timeout: options.write_timeout,
+            codec: options.codec,
+        })
+    }
 
-        tokio::spawn(async move { Tcp::forward_messages(receiver, forward_to).await });
+    /// Asks the server to use `codec` with a JSON handshake as the first frame,
+    /// which the server answers with the codec it uses from then on
+    async fn negotiate_codec(
+        framed: &mut Framed<S, LengthDelimitedCodec>,
+        codec: Codec,
+    ) -> Result<(), Error> {
+        let handshake = serde_json::to_vec(&json!({ "codec": codec.name() }))?;
+        framed.send(Bytes::from(handshake)).await?;
+
+        let answer = match framed.next().await {
+            Some(answer) => answer?,
+            None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+        let answer: Value = serde_json::from_slice(&answer)?;
+
+        if answer.get("codec").and_then(Value::as_str) != Some(codec.name()) {
+            return Err(Error::UnsupportedCodec(codec.name()));
+        }
 
-        Ok(Self { sender })
+        Ok(())
     }
 
     async fn forward_messages(
         mut receiver: SplitStream<Framed<S, LengthDelimitedCodec>>,
+        sender: FrameSender<S>,
+        mut liveness: Liveness,
+        write_timeout: Option<Duration>,
+        codec: Codec,
         forward_to: mpsc::Sender<Bytes>,
     ) {
-        while let Some(msg) = receiver.next().await {
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.next() => msg,
+                silence = liveness.silence() => {
+                    match silence {
+                        Silence::Ping => {
+                            let sent = match BytesSerializer::serialize(codec, LiRpcRequest::heartbeat()) {
+                                Ok(ping) => write_within(write_timeout, sender.lock().await.send(ping)).await,
+                                Err(e) => Err(e),
+                            };
+                            if let Err(e) = sent {
+                                error!("Error sending heartbeat: {e}");
+                                return;
+                            }
+                            continue;
+                        }
+                        Silence::Idle => warn!("Closing idle TCP connection"),
+                        Silence::Unresponsive => warn!("Closing TCP connection, the server didn't answer the heartbeat"),
+                    }
+
+                    sender.lock().await.close().await.ok();
+                    return;
+                }
+            };
+            liveness.received();
+
             let msg = match msg {
-                Ok(m) => m,
-                Err(e) => {
+                Some(Ok(m)) => m,
+                Some(Err(e)) => {
                     error!("Error receiving TCP frame: {e}");
                     return;
                 }
+                None => return,
             };
 
             if let Err(e) = forward_to.send(msg.into()).await {
@@ -110,8 +210,15 @@ where
 {
     type Serializer = BytesSerializer;
 
-    async fn send(&mut self, message: impl Serialize) -> Result<(), Error> {
-        let raw_message = Self::Serializer::serialize(message)?;
-        Ok(self.sender.send(raw_message).await?)
+    fn send(&mut self, message: impl Serialize) -> impl Future<Output = Result<(), Error>> + Send {
+        let raw_message = Self::Serializer::serialize(self.codec, message);
+        async move {
+            let mut sender = self.sender.lock().await;
+            write_within(self.write_timeout, sender.send(raw_message?)).await
+        }
+    }
+
+    fn codec(&self) -> Codec {
+        self.codec
     }
 }
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use serde::Serialize;
+use tokio::{net::UnixStream, sync::mpsc};
+use tokio_util::bytes::Bytes;
+
+use crate::{
+    codec::Codec,
+    error::Error,
+    serializers::bytes_serializer::BytesSerializer,
+    transport::{ConnectionOptions, Transport, tcp::Tcp},
+};
+
+/// Transport over a Unix domain socket, using the same
+/// length-delimited framing as the TCP transport.
+pub struct Unix {
+    inner: Tcp<UnixStream>,
+}
+
+impl Unix {
+    pub async fn connect(
+        path: impl AsRef<Path>,
+        options: &ConnectionOptions,
+        forward_to: mpsc::Sender<Bytes>,
+    ) -> Result<Self, Error> {
+        let stream = UnixStream::connect(path).await?;
+        let inner = Tcp::setup_with_stream(stream, options, forward_to).await?;
+
+        Ok(Self { inner })
+    }
+}
+
+impl Transport<Bytes> for Unix {
+    type Serializer = BytesSerializer;
+
+    fn send(&mut self, message: impl Serialize) -> impl Future<Output = Result<(), Error>> + Send {
+        self.inner.send(message)
+    }
+
+    fn codec(&self) -> Codec {
+        self.inner.codec()
+    }
+}
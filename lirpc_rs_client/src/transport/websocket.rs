@@ -1,47 +1,152 @@
+use std::{sync::Arc, time::Duration};
+
 use futures::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
 use serde::Serialize;
-use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
-use tracing::error;
+use tokio::{
+    net::TcpStream,
+    sync::{Mutex, mpsc},
+};
+use tokio_tungstenite::{
+    Connector, MaybeTlsStream, WebSocketStream, connect_async_tls_with_config,
+    connect_async_with_config,
+    tungstenite::{
+        Bytes, Message, Utf8Bytes,
+        client::IntoClientRequest,
+        handshake::client::Request,
+        http::{HeaderValue, header::SEC_WEBSOCKET_PROTOCOL},
+        protocol::WebSocketConfig,
+    },
+};
+use tracing::{error, warn};
 
 use crate::{
+    codec::Codec,
     error::Error,
-    serializers::{Serializer, string_serializer::StringSerializer},
-    transport::Transport,
+    serializers::{Serializer, bytes_serializer::BytesSerializer},
+    transport::{ConnectionOptions, Liveness, Silence, Transport, write_within},
 };
 
+type MessageSender = Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>;
+
 pub struct Websocket {
-    sender: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    /// Shared with the task receiving messages, to close the connection when it is idle
+    sender: MessageSender,
+    write_timeout: Option<Duration>,
+    codec: Codec,
 }
 
 impl Websocket {
-    pub async fn connect(url: &str, forward_to: mpsc::Sender<String>) -> Result<Self, Error> {
-        let (stream, _) = connect_async(url).await?;
+    pub async fn connect(
+        url: &str,
+        options: &ConnectionOptions,
+        forward_to: mpsc::Sender<Bytes>,
+    ) -> Result<Self, Error> {
+        let (stream, _) = connect_async_with_config(
+            upgrade_request(url, options)?,
+            Some(websocket_config(options)),
+            false,
+        )
+        .await?;
+
+        Ok(Self::setup_with_stream(stream, options, forward_to))
+    }
+
+    /// Connects with a custom rustls config for `wss` urls, e.g. one trusting a private CA
+    /// or presenting a client certificate.
+    pub async fn connect_with_tls_config(
+        url: &str,
+        config: Arc<rustls::ClientConfig>,
+        options: &ConnectionOptions,
+        forward_to: mpsc::Sender<Bytes>,
+    ) -> Result<Self, Error> {
+        let (stream, _) = connect_async_tls_with_config(
+            upgrade_request(url, options)?,
+            Some(websocket_config(options)),
+            false,
+            Some(Connector::Rustls(config)),
+        )
+        .await?;
+
+        Ok(Self::setup_with_stream(stream, options, forward_to))
+    }
+
+    fn setup_with_stream(
+        stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+        options: &ConnectionOptions,
+        forward_to: mpsc::Sender<Bytes>,
+    ) -> Self {
         let (sender, receiver) = stream.split();
+        let sender = Arc::new(Mutex::new(sender));
 
-        tokio::spawn(async move { Self::forward_messages(receiver, forward_to).await });
+        let s = sender.clone();
+        let liveness = Liveness::new(options);
+        let write_timeout = options.write_timeout;
+        tokio::spawn(async move {
+            Self::forward_messages(receiver, s, liveness, write_timeout, forward_to).await
+        });
 
-        Ok(Self { sender })
+        Self {
+            sender,
+            write_timeout: options.write_timeout,
+            codec: options.codec,
+        }
     }
 
     async fn forward_messages(
         mut receiver: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-        forward_to: mpsc::Sender<String>,
+        sender: MessageSender,
+        mut liveness: Liveness,
+        write_timeout: Option<Duration>,
+        forward_to: mpsc::Sender<Bytes>,
     ) {
-        while let Some(msg) = receiver.next().await {
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.next() => msg,
+                silence = liveness.silence() => {
+                    match silence {
+                        Silence::Ping => {
+                            let mut sender = sender.lock().await;
+                            if let Err(e) = write_within(write_timeout, sender.send(Message::Ping(Bytes::new()))).await {
+                                error!("Error sending heartbeat: {e}");
+                                return;
+                            }
+                            continue;
+                        }
+                        Silence::Idle => warn!("Closing idle websocket connection"),
+                        Silence::Unresponsive => warn!("Closing websocket connection, the server didn't answer the heartbeat"),
+                    }
+
+                    sender.lock().await.close().await.ok();
+                    return;
+                }
+            };
+            liveness.received();
+
             let msg = match msg {
-                Ok(m) => m,
-                Err(e) => {
+                Some(Ok(m)) => m,
+                Some(Err(e)) => {
                     error!("Error receiving websocket message: {e}");
                     return;
                 }
+                None => return,
             };
 
             let text = match msg {
-                Message::Text(text) => text.to_string(),
+                Message::Text(text) => Bytes::from(text),
+                Message::Binary(bytes) => bytes,
+                Message::Ping(payload) => {
+                    let mut sender = sender.lock().await;
+                    if let Err(e) =
+                        write_within(write_timeout, sender.send(Message::Pong(payload))).await
+                    {
+                        error!("Error answering ping: {e}");
+                        return;
+                    }
+                    continue;
+                }
                 Message::Close(_) => return,
                 _ => continue,
             };
@@ -53,11 +158,46 @@ impl Websocket {
     }
 }
 
-impl Transport<String> for Websocket {
-    type Serializer = StringSerializer;
+impl Transport<Bytes> for Websocket {
+    type Serializer = BytesSerializer;
+
+    fn send(&mut self, message: impl Serialize) -> impl Future<Output = Result<(), Error>> + Send {
+        let raw_message = Self::Serializer::serialize(self.codec, message);
+        async move {
+            let message = match raw_message? {
+                raw if self.codec.is_binary() => Message::Binary(raw),
+                raw => Message::Text(
+                    Utf8Bytes::try_from(raw).map_err(|e| Error::Codec(e.to_string()))?,
+                ),
+            };
+
+            let mut sender = self.sender.lock().await;
+            write_within(self.write_timeout, sender.send(message)).await
+        }
+    }
+
+    fn codec(&self) -> Codec {
+        self.codec
+    }
+}
+
+/// The upgrade request to `url`, asking for the codec as subprotocol unless it is JSON
+fn upgrade_request(url: &str, options: &ConnectionOptions) -> Result<Request, Error> {
+    let mut request = url.into_client_request()?;
 
-    async fn send(&mut self, message: impl Serialize) -> Result<(), Error> {
-        let raw_message = Self::Serializer::serialize(message)?;
-        Ok(self.sender.send(Message::text(raw_message)).await?)
+    if options.codec != Codec::Json {
+        let protocol = HeaderValue::from_str(&options.codec.subprotocol())
+            .map_err(|e| Error::Codec(e.to_string()))?;
+        request
+            .headers_mut()
+            .insert(SEC_WEBSOCKET_PROTOCOL, protocol);
     }
+
+    Ok(request)
+}
+
+fn websocket_config(options: &ConnectionOptions) -> WebSocketConfig {
+    WebSocketConfig::default()
+        .max_message_size(Some(options.max_message_size))
+        .max_frame_size(Some(options.max_message_size))
 }
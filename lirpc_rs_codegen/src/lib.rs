@@ -39,6 +39,14 @@ impl RustCodeGen {
             use serde::{Deserialize, Serialize};
         });
 
+        let (name, version, fingerprint) = (&spec.name, &spec.version, spec.fingerprint());
+        let identity = Self::pretty_print(quote! {
+            /// The api spec this client was generated from, to pass to `Client::hello`
+            pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+                lirpc_rs_client::ApiIdentity::new(#name, #version, #fingerprint)
+            }
+        });
+
         let mut type_names: Vec<&String> = spec.types.keys().collect();
         type_names.sort();
         let types = type_names
@@ -53,7 +61,8 @@ impl RustCodeGen {
 
         // Sections are pretty-printed independently and joined by a blank line,
         // since blank lines don't survive the quote!/syn token-stream round trip.
-        std::iter::once(imports)
+        [imports, identity]
+            .into_iter()
             .chain(types)
             .chain(methods)
             .collect::<Vec<String>>()
@@ -191,21 +200,43 @@ impl RustCodeGen {
     }
 
     fn method_to_tokens(name: &str, spec: &LiRpcMethodSpec) -> TokenStream {
+        if spec.notification {
+            return Self::notification_method_to_tokens(name, spec);
+        }
+
+        if let Some(stream_type) = &spec.stream {
+            return Self::stream_method_to_tokens(name, spec, stream_type);
+        }
+
         let fn_ident = format_ident!("{name}");
         let return_type = Self::type_to_tokens(&spec.returns);
 
+        let (result_type, resolve) = match &spec.error {
+            Some(error) => {
+                let error_type = Self::type_to_tokens(error);
+                (
+                    quote! { Result<#return_type, lirpc_rs_client::error::CallError<#error_type>> },
+                    quote! { resolve_with_error::<#error_type>() },
+                )
+            }
+            None => (
+                quote! { Result<#return_type, lirpc_rs_client::error::Error> },
+                quote! { resolve() },
+            ),
+        };
+
         match spec.messages.as_slice() {
             [] => quote! {
                 pub async fn #fn_ident<T, F>(
                     client: &mut Client<T, F>,
-                ) -> Result<#return_type, lirpc_rs_client::error::Error>
+                ) -> #result_type
                 where
                     T: Transport<F>,
                 {
                     client
                         .call::<(), #return_type>(#name.to_string(), None)
                         .await?
-                        .resolve()
+                        .#resolve
                         .await
                 }
             },
@@ -216,14 +247,97 @@ impl RustCodeGen {
                     pub async fn #fn_ident<T, F>(
                         client: &mut Client<T, F>,
                         request: #request_type,
-                    ) -> Result<#return_type, lirpc_rs_client::error::Error>
+                    ) -> #result_type
                     where
                         T: Transport<F>,
                     {
                         client
                             .call::<#request_type, #return_type>(#name.to_string(), Some(request))
                             .await?
-                            .resolve()
+                            .#resolve
+                            .await
+                    }
+                }
+            }
+            messages => panic!(
+                "method '{name}' has {} messages, but codegen only supports methods with 0 or 1 messages",
+                messages.len()
+            ),
+        }
+    }
+
+    fn notification_method_to_tokens(name: &str, spec: &LiRpcMethodSpec) -> TokenStream {
+        let fn_ident = format_ident!("notify_{name}");
+
+        match spec.messages.as_slice() {
+            [] => quote! {
+                pub async fn #fn_ident<T, F>(
+                    client: &mut Client<T, F>,
+                ) -> Result<(), lirpc_rs_client::error::Error>
+                where
+                    T: Transport<F>,
+                {
+                    client.notify::<()>(#name.to_string(), None).await
+                }
+            },
+            [message] => {
+                let request_type = Self::type_to_tokens(message);
+
+                quote! {
+                    pub async fn #fn_ident<T, F>(
+                        client: &mut Client<T, F>,
+                        request: #request_type,
+                    ) -> Result<(), lirpc_rs_client::error::Error>
+                    where
+                        T: Transport<F>,
+                    {
+                        client
+                            .notify::<#request_type>(#name.to_string(), Some(request))
+                            .await
+                    }
+                }
+            }
+            messages => panic!(
+                "method '{name}' has {} messages, but codegen only supports methods with 0 or 1 messages",
+                messages.len()
+            ),
+        }
+    }
+
+    fn stream_method_to_tokens(
+        name: &str,
+        spec: &LiRpcMethodSpec,
+        stream_type: &Type,
+    ) -> TokenStream {
+        let fn_ident = format_ident!("{name}");
+        let item_type = Self::type_to_tokens(stream_type);
+
+        match spec.messages.as_slice() {
+            [] => quote! {
+                pub async fn #fn_ident<T, F>(
+                    client: &mut Client<T, F>,
+                ) -> Result<lirpc_rs_client::CallStream<#item_type>, lirpc_rs_client::error::Error>
+                where
+                    T: Transport<F>,
+                {
+                    client
+                        .call_stream::<(), #item_type>(#name.to_string(), None)
+                        .await
+                }
+            },
+            [message] => {
+                let request_type = Self::type_to_tokens(message);
+
+                quote! {
+                    pub async fn #fn_ident<T, F>(
+                        client: &mut Client<T, F>,
+                        request: #request_type,
+                    ) -> Result<lirpc_rs_client::CallStream<#item_type>, lirpc_rs_client::error::Error>
+                    where
+                        T: Transport<F>,
+                    {
+                        client
+                            .call_stream::<#request_type, #item_type>(#name.to_string(), Some(request))
                             .await
                     }
                 }
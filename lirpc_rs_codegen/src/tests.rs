@@ -23,6 +23,11 @@ serde = { version = "1.0.228", features = ["derive"] }
 
 const EMPTY_LIB_RS: &str = r#"use lirpc_rs_client::{Client, transport::Transport};
 use serde::{Deserialize, Serialize};
+
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("my-app", "0.1.0", "b30cdcfb5d3e3f6d")
+}
 "#;
 
 #[test]
@@ -58,6 +63,11 @@ serde = { version = "1.0.228", features = ["derive"] }
 const GREETER_LIB_RS: &str = r#"use lirpc_rs_client::{Client, transport::Transport};
 use serde::{Deserialize, Serialize};
 
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("greeter", "0.1.0", "baeaa7dbb10cf56f")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GreetingRequest {
     pub name: String,
@@ -93,6 +103,9 @@ fn test_greeter_api_spec() {
             LiRpcMethodSpec {
                 messages: vec![Type::TypeRef("GreetingRequest".to_string())],
                 returns: Type::TypeRef("GreetingResponse".to_string()),
+                error: None,
+                stream: None,
+                notification: false,
             },
         )]),
         BTreeMap::from([
@@ -129,6 +142,11 @@ fn test_greeter_api_spec() {
 const AUTH_LIB_RS: &str = r#"use lirpc_rs_client::{Client, transport::Transport};
 use serde::{Deserialize, Serialize};
 
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("auth", "0.1.0", "eb36ba1ad790e367")
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthMessage {
     pub username: String,
@@ -188,6 +206,9 @@ fn test_auth_like_api_spec() {
                         Box::new(Type::Unit),
                         Box::new(Type::TypeRef("MyError".to_string())),
                     ),
+                    error: None,
+                    stream: None,
+                    notification: false,
                 },
             ),
             (
@@ -195,6 +216,9 @@ fn test_auth_like_api_spec() {
                 LiRpcMethodSpec {
                     messages: vec![],
                     returns: Type::TypeRef("SecretMessage".to_string()),
+                    error: None,
+                    stream: None,
+                    notification: false,
                 },
             ),
         ]),
@@ -239,3 +263,202 @@ fn test_auth_like_api_spec() {
 
     assert_eq!(lib_rs, AUTH_LIB_RS);
 }
+
+const STREAMING_LIB_RS: &str = r#"use lirpc_rs_client::{Client, transport::Transport};
+use serde::{Deserialize, Serialize};
+
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("streaming", "0.1.0", "4e3da347d52a8b92")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLine {
+    pub line: String,
+}
+
+pub async fn tail_log<T, F>(
+    client: &mut Client<T, F>,
+    request: String,
+) -> Result<lirpc_rs_client::CallStream<LogLine>, lirpc_rs_client::error::Error>
+where
+    T: Transport<F>,
+{
+    client.call_stream::<String, LogLine>("tail_log".to_string(), Some(request)).await
+}
+"#;
+
+#[test]
+fn test_streaming_api_spec() {
+    let spec = ApiSpec::new(
+        "streaming".to_string(),
+        "0.1.0".to_string(),
+        BTreeMap::from([(
+            "tail_log".to_string(),
+            LiRpcMethodSpec {
+                messages: vec![Type::String],
+                returns: Type::Unit,
+                error: None,
+                stream: Some(Type::TypeRef("LogLine".to_string())),
+                notification: false,
+            },
+        )]),
+        BTreeMap::from([(
+            "LogLine".to_string(),
+            TypeDefinition::Struct(Box::new(StructDefinition {
+                ident: "LogLine".to_string(),
+                fields: StructFields::Named(vec![("line".to_string(), Type::String)]),
+                generics: vec![],
+            })),
+        )]),
+    )
+    .unwrap();
+
+    let mut package = RustCodeGen::generate_package(&spec);
+
+    let lib_rs = package.remove("src/lib.rs").unwrap();
+
+    assert_eq!(lib_rs, STREAMING_LIB_RS);
+}
+
+const FALLIBLE_LIB_RS: &str = r#"use lirpc_rs_client::{Client, transport::Transport};
+use serde::{Deserialize, Serialize};
+
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("fallible", "0.1.0", "c025a76eab8813e0")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthMessage {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MyError {
+    AuthFailure,
+}
+
+pub async fn login<T, F>(
+    client: &mut Client<T, F>,
+    request: AuthMessage,
+) -> Result<(), lirpc_rs_client::error::CallError<MyError>>
+where
+    T: Transport<F>,
+{
+    client
+        .call::<AuthMessage, ()>("login".to_string(), Some(request))
+        .await?
+        .resolve_with_error::<MyError>()
+        .await
+}
+"#;
+
+#[test]
+fn test_method_with_error_type() {
+    let spec = ApiSpec::new(
+        "fallible".to_string(),
+        "0.1.0".to_string(),
+        BTreeMap::from([(
+            "login".to_string(),
+            LiRpcMethodSpec {
+                messages: vec![Type::TypeRef("AuthMessage".to_string())],
+                returns: Type::Unit,
+                error: Some(Type::TypeRef("MyError".to_string())),
+                stream: None,
+                notification: false,
+            },
+        )]),
+        BTreeMap::from([
+            (
+                "AuthMessage".to_string(),
+                TypeDefinition::Struct(Box::new(StructDefinition {
+                    ident: "AuthMessage".to_string(),
+                    fields: StructFields::Named(vec![("username".to_string(), Type::String)]),
+                    generics: vec![],
+                })),
+            ),
+            (
+                "MyError".to_string(),
+                TypeDefinition::Enum(Box::new(EnumDefinition::new(
+                    "MyError".to_string(),
+                    vec![EnumVariant::new_unit("AuthFailure".to_string())],
+                    vec![],
+                ))),
+            ),
+        ]),
+    )
+    .unwrap();
+
+    let mut package = RustCodeGen::generate_package(&spec);
+
+    let lib_rs = package.remove("src/lib.rs").unwrap();
+
+    assert_eq!(lib_rs, FALLIBLE_LIB_RS);
+}
+
+const NOTIFICATION_LIB_RS: &str = r#"use lirpc_rs_client::{Client, transport::Transport};
+use serde::{Deserialize, Serialize};
+
+/// The api spec this client was generated from, to pass to `Client::hello`
+pub fn api_identity() -> lirpc_rs_client::ApiIdentity {
+    lirpc_rs_client::ApiIdentity::new("notifying", "0.1.0", "48063ae304f85e89")
+}
+
+pub async fn notify_log<T, F>(
+    client: &mut Client<T, F>,
+    request: String,
+) -> Result<(), lirpc_rs_client::error::Error>
+where
+    T: Transport<F>,
+{
+    client.notify::<String>("log".to_string(), Some(request)).await
+}
+
+pub async fn notify_ping_me<T, F>(
+    client: &mut Client<T, F>,
+) -> Result<(), lirpc_rs_client::error::Error>
+where
+    T: Transport<F>,
+{
+    client.notify::<()>("ping_me".to_string(), None).await
+}
+"#;
+
+#[test]
+fn test_notification_api_spec() {
+    let spec = ApiSpec::new(
+        "notifying".to_string(),
+        "0.1.0".to_string(),
+        BTreeMap::from([
+            (
+                "log".to_string(),
+                LiRpcMethodSpec {
+                    messages: vec![Type::String],
+                    returns: Type::Unit,
+                    error: None,
+                    stream: None,
+                    notification: true,
+                },
+            ),
+            (
+                "ping_me".to_string(),
+                LiRpcMethodSpec {
+                    messages: vec![],
+                    returns: Type::Unit,
+                    error: None,
+                    stream: None,
+                    notification: true,
+                },
+            ),
+        ]),
+        BTreeMap::new(),
+    )
+    .unwrap();
+
+    let mut package = RustCodeGen::generate_package(&spec);
+
+    let lib_rs = package.remove("src/lib.rs").unwrap();
+
+    assert_eq!(lib_rs, NOTIFICATION_LIB_RS);
+}
@@ -1,7 +1,7 @@
 import assert from "node:assert/strict";
 import { test } from "node:test";
 import { Client } from "./client.js";
-import { LiRpcConnectionClosedError, LiRpcServerError } from "./error.js";
+import { LiRpcConnectionClosedError, LiRpcDomainError, LiRpcProtocolError } from "./error.js";
 
 type Listener = (event: any) => void;
 
@@ -93,11 +93,35 @@ test("omitted payload is sent as null, not omitted", async () => {
   });
 });
 
-test("an err response rejects with LiRpcServerError carrying error/detail", async () => {
+test("a protocol_error response rejects with LiRpcProtocolError carrying error/detail", async () => {
   await withFakeWebsocket(async () => {
     const { client, socket } = await connectFakeClient();
 
     const callPromise = client.call("login", { username: "cas" });
+    socket.message(
+      JSON.stringify({
+        headers: { id: 1, res: "err", protocol_error: true },
+        payload: { error: "unknown_method", detail: "login" },
+      }),
+    );
+
+    await assert.rejects(callPromise, (error: unknown) => {
+      if (!(error instanceof LiRpcProtocolError)) {
+        throw new Error("expected a LiRpcProtocolError");
+      }
+      assert.equal(error.errorType, "unknown_method");
+      assert.equal(error.detail, "login");
+      return true;
+    });
+  });
+});
+
+test("an err response without protocol_error rejects with LiRpcDomainError", async () => {
+  await withFakeWebsocket(async () => {
+    const { client, socket } = await connectFakeClient();
+
+    const callPromise = client.call("login", { username: "cas" });
+    // Shaped like a protocol error, but returned by the method itself
     socket.message(
       JSON.stringify({
         headers: { id: 1, res: "err" },
@@ -106,11 +130,10 @@ test("an err response rejects with LiRpcServerError carrying error/detail", asyn
     );
 
     await assert.rejects(callPromise, (error: unknown) => {
-      if (!(error instanceof LiRpcServerError)) {
-        throw new Error("expected a LiRpcServerError");
+      if (!(error instanceof LiRpcDomainError)) {
+        throw new Error("expected a LiRpcDomainError");
       }
-      assert.equal(error.errorType, "AuthFailure");
-      assert.equal(error.detail, "bad credentials");
+      assert.deepEqual(error.payload, { error: "AuthFailure", detail: "bad credentials" });
       return true;
     });
   });
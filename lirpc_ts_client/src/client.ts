@@ -1,4 +1,9 @@
-import { LiRpcConnectionClosedError, LiRpcSerdeError, LiRpcServerError } from "./error.js";
+import {
+  LiRpcConnectionClosedError,
+  LiRpcDomainError,
+  LiRpcProtocolError,
+  LiRpcSerdeError,
+} from "./error.js";
 import {
   isLiRpcServerErrorPayload,
   isOk,
@@ -72,10 +77,12 @@ export class Client<F> {
     pending.delete(response.headers.id);
 
     if (!isOk(response.headers)) {
-      if (isLiRpcServerErrorPayload(response.payload)) {
-        call.reject(new LiRpcServerError(response.payload.error, response.payload.detail));
+      if (!response.headers.protocol_error) {
+        call.reject(new LiRpcDomainError(response.payload));
+      } else if (isLiRpcServerErrorPayload(response.payload)) {
+        call.reject(new LiRpcProtocolError(response.payload.error, response.payload.detail));
       } else {
-        call.reject(new LiRpcSerdeError("Server error response did not match expected shape"));
+        call.reject(new LiRpcSerdeError("Protocol error response did not match expected shape"));
       }
       return;
     }
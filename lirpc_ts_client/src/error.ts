@@ -23,13 +23,34 @@ export class LiRpcWebsocketError extends LiRpcError {
   }
 }
 
-/** The server responded with `res: "err"`. Mirrors `Error::Server { error, detail }`. */
-export class LiRpcServerError extends LiRpcError {
+/**
+ * The server responded with `res: "err"` and the `protocol_error` header, e.g. because the method
+ * is unknown or the request was malformed. Mirrors `Error::Protocol(ProtocolError)`.
+ *
+ * `detail` is a string for every protocol error except `extractor_rejection`, where it holds the
+ * error returned by the extractor.
+ */
+export class LiRpcProtocolError extends LiRpcError {
   constructor(
     readonly errorType: string,
-    readonly detail: string,
+    readonly detail: unknown,
   ) {
-    super(`ServerError: ${errorType}: ${detail}`);
+    super(`ProtocolError: ${errorType}: ${typeof detail === "string" ? detail : JSON.stringify(detail)}`);
+  }
+}
+
+/** @deprecated Renamed to `LiRpcProtocolError`, which method errors are no longer reported as. */
+export const LiRpcServerError = LiRpcProtocolError;
+/** @deprecated Renamed to `LiRpcProtocolError`. */
+export type LiRpcServerError = LiRpcProtocolError;
+
+/**
+ * The method itself responded with an error (`res: "err"` without the `protocol_error` header),
+ * e.g. the `Err` of a handler returning a `Result`. Mirrors `Error::Domain`.
+ */
+export class LiRpcDomainError extends LiRpcError {
+  constructor(readonly payload: unknown) {
+    super(`The method responded with an error: ${JSON.stringify(payload)}`);
   }
 }
 
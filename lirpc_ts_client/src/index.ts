@@ -7,7 +7,9 @@ export {
   LiRpcError,
   LiRpcSerdeError,
   LiRpcWebsocketError,
+  LiRpcProtocolError,
   LiRpcServerError,
+  LiRpcDomainError,
   LiRpcConnectionClosedError,
 } from "./error.js";
 export type {
@@ -26,6 +26,14 @@ test("toLiRpcResponse rejects frames missing a numeric header id", () => {
   assert.equal(toLiRpcResponse({ headers: { id: "1" } }), null);
 });
 
+test("toLiRpcResponse keeps the protocol_error header and rejects non-boolean values", () => {
+  assert.deepEqual(toLiRpcResponse({ headers: { id: 3, res: "err", protocol_error: true } }), {
+    headers: { id: 3, res: "err", protocol_error: true },
+    payload: undefined,
+  });
+  assert.equal(toLiRpcResponse({ headers: { id: 3, protocol_error: "yes" } }), null);
+});
+
 test("toLiRpcResponse rejects frames with an invalid res value", () => {
   assert.equal(toLiRpcResponse({ headers: { id: 1, res: "maybe" } }), null);
 });
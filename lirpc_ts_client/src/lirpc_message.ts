@@ -3,10 +3,11 @@
  * `lirpc::lirpc_message`.
  *
  * Request:  {"headers":{"id":<u32>,"function":<string>},"payload":<P>|null}
- * Response: {"headers":{"id":<u32>,"res"?:"ok"|"err"},"payload"?:<P>}
+ * Response: {"headers":{"id":<u32>,"res"?:"ok"|"err","protocol_error"?:true},"payload"?:<P>}
  *
  * `res` is omitted by the server on success (absent means "ok"); `payload` is omitted on the
- * response when there is none.
+ * response when there is none. `protocol_error` is only set on errors of the protocol itself,
+ * to tell them apart from errors returned by the method.
  */
 
 export interface LiRpcRequestHeaders {
@@ -24,6 +25,7 @@ export type LiRpcResponseResult = "ok" | "err";
 export interface LiRpcResponseHeaders {
   id: number;
   res?: LiRpcResponseResult;
+  protocol_error?: boolean;
 }
 
 export interface LiRpcResponse {
@@ -31,10 +33,10 @@ export interface LiRpcResponse {
   payload?: unknown;
 }
 
-/** Mirrors `LiRpcServerError { error: String, detail: String }`. */
+/** Mirrors `LiRpcProtocolError`, serialized as `{"error": <kind>, "detail": <detail>}`. */
 export interface LiRpcServerErrorPayload {
   error: string;
-  detail: string;
+  detail: unknown;
 }
 
 export function isOk(headers: LiRpcResponseHeaders): boolean {
@@ -56,22 +58,25 @@ export function toLiRpcResponse(value: unknown): LiRpcResponse | null {
     return null;
   }
 
-  const { id, res } = value.headers;
+  const { id, res, protocol_error } = value.headers;
   if (typeof id !== "number") {
     return null;
   }
   if (res !== undefined && res !== "ok" && res !== "err") {
     return null;
   }
+  if (protocol_error !== undefined && typeof protocol_error !== "boolean") {
+    return null;
+  }
 
   return {
-    headers: { id, res },
+    headers: protocol_error === undefined ? { id, res } : { id, res, protocol_error },
     payload: "payload" in value ? value.payload : undefined,
   };
 }
 
 export function isLiRpcServerErrorPayload(value: unknown): value is LiRpcServerErrorPayload {
   return (
-    isRecord(value) && typeof value.error === "string" && typeof value.detail === "string"
+    isRecord(value) && typeof value.error === "string" && "detail" in value
   );
 }